@@ -0,0 +1,184 @@
+//! Amiga trackdisk format: MFM sectors behind a 4489 4489 sync, with every
+//! longword split into its odd and even bits.
+
+use std::fmt;
+use crate::DiskType;
+
+/// A sector found on a track, with its header fields and 512 data bytes.
+#[derive(Debug, Clone)]
+pub struct AmigaSector {
+    pub format: u8,
+    /// Track number, cyl * 2 + head
+    pub track: u8,
+    pub sector: u8,
+    pub sectors_to_gap: u8,
+    pub label: [u8; 16],
+    pub header_ok: bool,
+    pub data: Vec<u8>,
+    pub data_ok: bool,
+    /// Bitcell offset of the sector header within the revolution
+    pub position: usize,
+}
+
+/// Whether a sector was read intact, or which checksum failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AmigaStatus {
+    Ok,
+    BadHeaderChecksum,
+    BadDataChecksum,
+}
+
+impl fmt::Display for AmigaStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmigaStatus::Ok => write!(f, "ok"),
+            AmigaStatus::BadHeaderChecksum => write!(f, "bad header checksum"),
+            AmigaStatus::BadDataChecksum => write!(f, "bad data checksum"),
+        }
+    }
+}
+
+impl AmigaSector {
+    /// The first problem found with the sector, checking the header first.
+    pub fn status(&self) -> AmigaStatus {
+        if !self.header_ok {
+            AmigaStatus::BadHeaderChecksum
+        } else if !self.data_ok {
+            AmigaStatus::BadDataChecksum
+        } else {
+            AmigaStatus::Ok
+        }
+    }
+}
+
+/// Bitcell period in ns and sectors per track of the Amiga disk types, or
+/// `None` for other disk types.
+pub fn layout(disk_type: DiskType) -> Option<(f64, usize)> {
+    match disk_type {
+        DiskType::Amiga => Some((2000.0, 11)),
+        DiskType::AmigaHd => Some((1000.0, 22)),
+        _ => None,
+    }
+}
+
+const DATA_BITS: u32 = 0x5555_5555;
+// info and label longwords, then header checksum, data checksum and data
+const INFO_LONGS: usize = 1;
+const LABEL_LONGS: usize = 4;
+const DATA_LONGS: usize = 128;
+const SECTOR_CELLS: usize = (2 * (INFO_LONGS + LABEL_LONGS + 2 + DATA_LONGS)) * 32;
+
+fn raw_long(bits: &[bool], pos: usize) -> u32 {
+    bits[pos..pos + 32].iter().fold(0, |long, &bit| (long << 1) | bit as u32)
+}
+
+// Reads count longwords stored as a block of odd bits followed by a block of
+// even bits, returning them decoded with the checksum of the raw block.
+fn read_longs(bits: &[bool], pos: usize, count: usize) -> (Vec<u32>, u32) {
+    let mut checksum = 0;
+    let longs = (0..count).map(|i| {
+        let odd = raw_long(bits, pos + i * 32);
+        let even = raw_long(bits, pos + (count + i) * 32);
+        checksum ^= odd ^ even;
+        ((odd & DATA_BITS) << 1) | (even & DATA_BITS)
+    }).collect();
+    (longs, checksum & DATA_BITS)
+}
+
+/// Decodes the sectors in the bitcells of one revolution (see
+/// `flux::decode_revolution`), in the order found.
+pub fn decode_sectors(bits: &[bool]) -> Vec<AmigaSector> {
+    let mut sectors = Vec::new();
+    let mut shift: u32 = 0;
+    let mut i = 0;
+    while i < bits.len() {
+        shift = (shift << 1) | bits[i] as u32;
+        i += 1;
+        if shift != 0x4489_4489 || i + SECTOR_CELLS > bits.len() {
+            continue;
+        }
+        let mut pos = i;
+        let (info, info_sum) = read_longs(bits, pos, INFO_LONGS);
+        pos += INFO_LONGS * 64;
+        let (label, label_sum) = read_longs(bits, pos, LABEL_LONGS);
+        pos += LABEL_LONGS * 64;
+        let (header_sum, _) = read_longs(bits, pos, 1);
+        let (data_sum, _) = read_longs(bits, pos + 64, 1);
+        pos += 128;
+        let (data, computed_data_sum) = read_longs(bits, pos, DATA_LONGS);
+        let [format, track, sector, sectors_to_gap] = info[0].to_be_bytes();
+        let label: Vec<u8> = label.iter().flat_map(|long| long.to_be_bytes()).collect();
+        sectors.push(AmigaSector {
+            format,
+            track,
+            sector,
+            sectors_to_gap,
+            label: label.try_into().unwrap(),
+            header_ok: header_sum[0] == info_sum ^ label_sum,
+            data: data.iter().flat_map(|long| long.to_be_bytes()).collect(),
+            data_ok: data_sum[0] == computed_data_sum,
+            position: i,
+        });
+        i += SECTOR_CELLS;
+        shift = 0;
+    }
+    sectors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MFM encodes the data bits of a longword masked with DATA_BITS
+    fn mfm_long(bits: &mut Vec<bool>, long: u32) {
+        for i in (0..16).rev() {
+            let data = long >> (i * 2) & 1 != 0;
+            let clock = !data && !bits.last().copied().unwrap_or(false);
+            bits.extend([clock, data]);
+        }
+    }
+
+    // Writes longwords as a block of odd bits and a block of even bits,
+    // returning their checksum.
+    fn write_longs(bits: &mut Vec<bool>, longs: &[u32]) -> u32 {
+        longs.iter().for_each(|long| mfm_long(bits, long >> 1 & DATA_BITS));
+        longs.iter().for_each(|long| mfm_long(bits, long & DATA_BITS));
+        longs.iter().fold(0, |sum, long| sum ^ (long >> 1 ^ long) & DATA_BITS)
+    }
+
+    fn encode_sector(bits: &mut Vec<bool>, track: u8, sector: u8, data: &[u32], bad_header: bool, bad_data: bool) {
+        write_longs(bits, &[0, 0]);
+        bits.extend((0..32).rev().map(|i| 0x4489_4489u32 >> i & 1 != 0));
+        let info = u32::from_be_bytes([0xff, track, sector, 11 - sector]);
+        let mut header = vec![];
+        let header_sum = write_longs(&mut header, &[info]) ^ write_longs(&mut header, &[0; LABEL_LONGS]);
+        bits.extend(header);
+        let mut data_bits = vec![];
+        let data_sum = write_longs(&mut data_bits, data);
+        write_longs(bits, &[header_sum ^ bad_header as u32]);
+        write_longs(bits, &[data_sum ^ bad_data as u32]);
+        bits.extend(data_bits);
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = |sector: u32| -> Vec<u32> { (0..128).map(|i| 0x8421_f00d ^ (sector << 24) ^ (i * 0x0101_0107)).collect() };
+        let mut bits = Vec::new();
+        for sector in 0..11 {
+            encode_sector(&mut bits, 81, sector, &data(sector as u32), sector == 5, sector == 3);
+        }
+        let sectors = decode_sectors(&bits);
+        assert_eq!(sectors.len(), 11);
+        for (i, sector) in sectors.iter().enumerate() {
+            assert_eq!((sector.format, sector.track, sector.sector, sector.sectors_to_gap), (0xff, 81, i as u8, 11 - i as u8));
+            let expected = match i {
+                3 => AmigaStatus::BadDataChecksum,
+                5 => AmigaStatus::BadHeaderChecksum,
+                _ => AmigaStatus::Ok,
+            };
+            assert_eq!(sector.status(), expected);
+            let bytes: Vec<u8> = data(i as u32).iter().flat_map(|long| long.to_be_bytes()).collect();
+            assert_eq!(sector.data, bytes);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Commodore 1541 GCR format: each nibble written as 5 bits, with blocks
+//! behind a sync of 10 or more 1 bits and the bit rate stepping down in four
+//! speed zones towards the hub.
+
+use std::fmt;
+use crate::{ScpFlags, ScpHeader};
+
+/// A sector found on a track, from its header block and the data block after
+/// it.
+#[derive(Debug, Clone)]
+pub struct GcrSector {
+    pub track: u8,
+    pub sector: u8,
+    /// Disk ID as stored, second character first
+    pub id: [u8; 2],
+    pub header_ok: bool,
+    pub data: Option<Vec<u8>>,
+    pub data_ok: bool,
+    /// Bitcell offset of the header block within the revolution
+    pub position: usize,
+}
+
+/// Whether a sector was read intact, or what went wrong.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GcrStatus {
+    Ok,
+    BadHeaderChecksum,
+    /// Header found but no data block following it
+    NoData,
+    BadDataChecksum,
+}
+
+impl fmt::Display for GcrStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GcrStatus::Ok => write!(f, "ok"),
+            GcrStatus::BadHeaderChecksum => write!(f, "bad header checksum"),
+            GcrStatus::NoData => write!(f, "no data"),
+            GcrStatus::BadDataChecksum => write!(f, "bad data checksum"),
+        }
+    }
+}
+
+impl GcrSector {
+    /// The first problem found with the sector, checking the header first.
+    pub fn status(&self) -> GcrStatus {
+        if !self.header_ok {
+            GcrStatus::BadHeaderChecksum
+        } else if self.data.is_none() {
+            GcrStatus::NoData
+        } else if !self.data_ok {
+            GcrStatus::BadDataChecksum
+        } else {
+            GcrStatus::Ok
+        }
+    }
+}
+
+/// Bitcell period in ns and sectors per track of a 1541 track, numbered
+/// from 1.
+pub fn zone(track: u8) -> (f64, usize) {
+    match track {
+        0..=17 => (3250.0, 21),
+        18..=24 => (3500.0, 19),
+        25..=30 => (3750.0, 18),
+        _ => (4000.0, 17),
+    }
+}
+
+/// The 1541 track held in an SCP track slot, and whether it is a half-track
+/// above it. 96 TPI images step a half-track per cylinder, 48 TPI images a
+/// whole track.
+pub fn slot_track(header: &ScpHeader, slot: usize) -> (u8, bool) {
+    let cyl = slot / 2;
+    match header.flags.contains(ScpFlags::TPI_96) {
+        true => ((cyl / 2 + 1) as u8, cyl % 2 == 1),
+        false => ((cyl + 1) as u8, false),
+    }
+}
+
+const HEADER_BLOCK: u8 = 0x08;
+const DATA_BLOCK: u8 = 0x07;
+const SYNC_BITS: usize = 10;
+// bitcells allowed between a header block and its data block
+const DATA_WINDOW: usize = 64 * 10;
+
+// 5-bit GCR codes of each nibble
+const GCR: [u8; 16] = [
+    0x0a, 0x0b, 0x12, 0x13, 0x0e, 0x0f, 0x16, 0x17,
+    0x09, 0x19, 0x1a, 0x1b, 0x0d, 0x1d, 0x1e, 0x15,
+];
+
+fn read_nibble(bits: &[bool], pos: usize) -> Option<u8> {
+    let code = bits[pos..pos + 5].iter().fold(0, |code, &bit| (code << 1) | bit as u8);
+    GCR.iter().position(|&gcr| gcr == code).map(|nibble| nibble as u8)
+}
+
+// Reads count bytes of 10 bitcells each, returning them with whether every
+// code was valid GCR. Invalid codes read as 0.
+fn read_bytes(bits: &[bool], pos: usize, count: usize) -> Option<(Vec<u8>, bool)> {
+    if pos + count * 10 > bits.len() {
+        return None;
+    }
+    let mut valid = true;
+    let bytes = (0..count).map(|i| {
+        let (high, low) = (read_nibble(bits, pos + i * 10), read_nibble(bits, pos + i * 10 + 5));
+        valid &= high.is_some() && low.is_some();
+        high.unwrap_or(0) << 4 | low.unwrap_or(0)
+    }).collect();
+    Some((bytes, valid))
+}
+
+/// Decodes the sectors in the bitcells of one revolution (see
+/// `flux::decode_revolution`), in the order found.
+pub fn decode_sectors(bits: &[bool]) -> Vec<GcrSector> {
+    let mut sectors: Vec<GcrSector> = Vec::new();
+    let mut last_header: Option<usize> = None;
+    let mut ones = 0;
+    for (pos, &bit) in bits.iter().enumerate() {
+        if bit {
+            ones += 1;
+            continue;
+        }
+        let synced = ones >= SYNC_BITS;
+        ones = 0;
+        if !synced {
+            continue;
+        }
+        let Some((block, _)) = read_bytes(bits, pos, 1) else { break };
+        match block[0] {
+            HEADER_BLOCK => {
+                let Some((header, valid)) = read_bytes(bits, pos, 6) else { break };
+                let [_, checksum, sector, track, id2, id1] = header[..] else { unreachable!() };
+                sectors.push(GcrSector {
+                    track,
+                    sector,
+                    id: [id2, id1],
+                    header_ok: valid && checksum == sector ^ track ^ id2 ^ id1,
+                    data: None,
+                    data_ok: false,
+                    position: pos,
+                });
+                last_header = Some(pos);
+            }
+            DATA_BLOCK => {
+                let Some(header_pos) = last_header.take() else { continue };
+                if pos - header_pos > DATA_WINDOW {
+                    continue;
+                }
+                let Some((data, valid)) = read_bytes(bits, pos, 258) else { break };
+                let checksum = data[1..257].iter().fold(0, |sum, byte| sum ^ byte);
+                let sector = sectors.last_mut().unwrap();
+                sector.data = Some(data[1..257].to_vec());
+                sector.data_ok = valid && checksum == data[257];
+            }
+            _ => {}
+        }
+    }
+    sectors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DiskType;
+
+    fn gcr_bytes(bits: &mut Vec<bool>, bytes: &[u8]) {
+        for nibble in bytes.iter().flat_map(|byte| [byte >> 4, byte & 0xf]) {
+            bits.extend((0..5).rev().map(|i| GCR[nibble as usize] >> i & 1 != 0));
+        }
+    }
+
+    fn block(bits: &mut Vec<bool>, bytes: &[u8]) {
+        bits.extend([true; 40]);
+        gcr_bytes(bits, bytes);
+        // gap of alternating bits, which never reads as a sync
+        bits.extend((0..72).map(|i| i % 2 == 1));
+    }
+
+    #[test]
+    fn round_trip() {
+        let (track, id) = (1, [b'A', b'B']);
+        let data = |sector: u8| -> Vec<u8> { (0..=255).map(|i: u8| i.wrapping_mul(31) ^ sector).collect() };
+        let mut bits = Vec::new();
+        for sector in 0..zone(track).1 as u8 {
+            let checksum = sector ^ track ^ id[1] ^ id[0];
+            block(&mut bits, &[HEADER_BLOCK, checksum, sector, track, id[1], id[0], 0x0f, 0x0f]);
+            let data = data(sector);
+            let checksum = data.iter().fold(0, |sum, byte| sum ^ byte) ^ (sector == 7) as u8;
+            block(&mut bits, &[&[DATA_BLOCK][..], &data, &[checksum, 0, 0]].concat());
+        }
+        let sectors = decode_sectors(&bits);
+        assert_eq!(sectors.len(), 21);
+        for (i, sector) in sectors.iter().enumerate() {
+            assert_eq!((sector.track, sector.sector, sector.id), (track, i as u8, [b'B', b'A']));
+            let expected = if i == 7 { GcrStatus::BadDataChecksum } else { GcrStatus::Ok };
+            assert_eq!(sector.status(), expected);
+            assert_eq!(sector.data, Some(data(i as u8)));
+        }
+    }
+
+    #[test]
+    fn zones() {
+        assert_eq!(zone(17), (3250.0, 21));
+        assert_eq!(zone(18), (3500.0, 19));
+        assert_eq!(zone(30), (3750.0, 18));
+        assert_eq!(zone(35), (4000.0, 17));
+    }
+
+    #[test]
+    fn half_tracks() {
+        let mut header = ScpHeader {
+            version: 0x22,
+            disk_type: DiskType::C64,
+            rev_count: 1,
+            start_track: 0,
+            end_track: 167,
+            flags: ScpFlags::empty(),
+            bitcell_time: 0,
+            heads: 1,
+            resolution: 0,
+            checksum: 0,
+            track_data_headers: [0; 168],
+        };
+        // 48 TPI: one 1541 track per cylinder
+        assert_eq!(slot_track(&header, 0), (1, false));
+        assert_eq!(slot_track(&header, 2), (2, false));
+        assert_eq!(slot_track(&header, 68), (35, false));
+        // 96 TPI: a half-track per cylinder
+        header.flags = ScpFlags::TPI_96;
+        assert_eq!(slot_track(&header, 0), (1, false));
+        assert_eq!(slot_track(&header, 2), (1, true));
+        assert_eq!(slot_track(&header, 4), (2, false));
+        assert_eq!(slot_track(&header, 70), (18, true));
+    }
+}
@@ -0,0 +1,123 @@
+// The diff subcommand: compares two images track by track.
+
+use clap::Args;
+use std::collections::BTreeMap;
+use std::error::Error;
+use scpweave::ibm::{decode_sectors, Encoding};
+use scpweave::flux::{pll_decode, PllConfig};
+use scpweave::weave::{detect_format, read_revs, RevFlux};
+use scpweave::ScpImage;
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    scp_a: String,
+    scp_b: String,
+
+    /// Decode some tracks as FM or MFM instead of detecting the encoding:
+    /// tracks=fm or tracks=mfm
+    #[arg(short('e'), long)]
+    encoding: Vec<String>,
+}
+
+// Sectors seen in any revolution by cyl, head and record, with the data of a
+// copy with good CRCs if there is one.
+fn sector_data(revs: &[&RevFlux], encoding: Encoding, cell_ns: f64) -> BTreeMap<(u8, u8, u8), Option<Vec<u8>>> {
+    let mut sectors = BTreeMap::new();
+    for rev in revs {
+        for sector in decode_sectors(&pll_decode(&rev.intervals, &PllConfig::new(cell_ns)), encoding) {
+            if !sector.id_ok {
+                continue;
+            }
+            let data = sectors.entry((sector.cyl, sector.head, sector.record)).or_insert(None);
+            if sector.data_ok && data.is_none() {
+                *data = sector.data;
+            }
+        }
+    }
+    sectors
+}
+
+fn identical(a: &ScpImage, b: &ScpImage, track: usize) -> Result<bool, Box<dyn Error>> {
+    let (a_track, b_track) = (a.tracks[track].as_ref().unwrap(), b.tracks[track].as_ref().unwrap());
+    if a.sample_bits() != b.sample_bits() || a.sample_ns() != b.sample_ns() || a_track.revs.len() != b_track.revs.len() {
+        return Ok(false);
+    }
+    for (rev, (a_rev, b_rev)) in a_track.revs.iter().zip(&b_track.revs).enumerate() {
+        if a_rev.duration != b_rev.duration || a.revolution_flux(track, rev)? != b.revolution_flux(track, rev)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn list(sectors: &[(u8, u8, u8)]) -> String {
+    sectors.iter().map(|(c, h, r)| format!("{c}.{h}.{r}")).collect::<Vec<_>>().join(" ")
+}
+
+pub fn run(args: DiffArgs) -> Result<(), Box<dyn Error>> {
+    let inputs = [ScpImage::open(&args.scp_a)?, ScpImage::open(&args.scp_b)?];
+    let encodings = crate::parse_encodings(&args.encoding, crate::combined_heads(&inputs))?;
+    let (mut same, mut differ) = (0, 0);
+    for (i, &encoding) in encodings.iter().enumerate() {
+        let name = format!("Track {i} ({}.{})", i / 2, i % 2);
+        match (&inputs[0].tracks[i], &inputs[1].tracks[i]) {
+            (None, None) => continue,
+            (Some(_), None) => {
+                println!("{name}: only in A");
+                differ += 1;
+                continue;
+            }
+            (None, Some(_)) => {
+                println!("{name}: only in B");
+                differ += 1;
+                continue;
+            }
+            (Some(_), Some(_)) => {}
+        }
+        if identical(&inputs[0], &inputs[1], i)? {
+            same += 1;
+            continue;
+        }
+        differ += 1;
+
+        let revs = read_revs(&inputs, i)?;
+        let (a_revs, b_revs): (Vec<_>, Vec<_>) = revs.iter().partition(|r| r.source == 0);
+        println!("{name}: {} revolutions in A, {} in B", a_revs.len(), b_revs.len());
+        for (a, b) in a_revs.iter().zip(&b_revs) {
+            let (a_len, b_len) = (a.intervals.len(), b.intervals.len());
+            println!("  rev {}: {:.3}ms {:.2} RPM vs {:.3}ms {:.2} RPM, {a_len} vs {b_len} transitions ({:+})",
+                     a.rev, a.duration_ns / 1e6, 60e9 / a.duration_ns, b.duration_ns / 1e6, 60e9 / b.duration_ns,
+                     b_len as i64 - a_len as i64);
+        }
+
+        let Some((encoding, cell_ns)) = detect_format(&revs, encoding) else { continue };
+        let (a, b) = (sector_data(&a_revs, encoding, cell_ns), sector_data(&b_revs, encoding, cell_ns));
+        let mut bad_in_a = Vec::new();
+        let mut bad_in_b = Vec::new();
+        let mut different = Vec::new();
+        for id in a.keys().chain(b.keys().filter(|id| !a.contains_key(id))) {
+            match (a.get(id).cloned().flatten(), b.get(id).cloned().flatten()) {
+                (Some(a_data), Some(b_data)) if a_data != b_data => different.push(*id),
+                (Some(_), None) => bad_in_b.push(*id),
+                (None, Some(_)) => bad_in_a.push(*id),
+                (None, None) => {
+                    bad_in_a.push(*id);
+                    bad_in_b.push(*id);
+                }
+                _ => {}
+            }
+        }
+        println!("  {encoding} {cell_ns:.0}ns cells, {} sectors in A, {} in B", a.len(), b.len());
+        if !bad_in_a.is_empty() {
+            println!("  bad or missing in A: {}", list(&bad_in_a));
+        }
+        if !bad_in_b.is_empty() {
+            println!("  bad or missing in B: {}", list(&bad_in_b));
+        }
+        if !different.is_empty() {
+            println!("  data differs: {}", list(&different));
+        }
+    }
+    println!("{same} tracks identical, {differ} differ");
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+
+macro_rules! disk_types {
+    ($($name:ident = $code:literal, $description:literal;)*) => {
+        /// Disk types defined by the SCP specification.
+        #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+        pub enum DiskType {
+            $($name,)*
+            Other(u8),
+        }
+
+        impl From<u8> for DiskType {
+            fn from(code: u8) -> Self {
+                match code {
+                    $($code => DiskType::$name,)*
+                    code => DiskType::Other(code),
+                }
+            }
+        }
+
+        impl From<DiskType> for u8 {
+            fn from(disk_type: DiskType) -> Self {
+                match disk_type {
+                    $(DiskType::$name => $code,)*
+                    DiskType::Other(code) => code,
+                }
+            }
+        }
+
+        impl fmt::Display for DiskType {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self {
+                    $(DiskType::$name => write!(f, $description),)*
+                    DiskType::Other(code) => write!(f, "unknown ({code:#04x})"),
+                }
+            }
+        }
+    };
+}
+
+disk_types! {
+    C64 = 0x00, "Commodore 64";
+    Amiga = 0x04, "Amiga";
+    AmigaHd = 0x08, "Amiga HD";
+    AtariFmSs = 0x10, "Atari 8-bit FM single sided";
+    AtariFmDs = 0x11, "Atari 8-bit FM double sided";
+    AtariFmEx = 0x12, "Atari 8-bit FM extended";
+    AtariStSs = 0x14, "Atari ST single sided";
+    AtariStDs = 0x15, "Atari ST double sided";
+    AppleII = 0x20, "Apple II";
+    AppleIIPro = 0x21, "Apple II Pro";
+    Apple400K = 0x24, "Apple 400K";
+    Apple800K = 0x25, "Apple 800K";
+    Apple1M44 = 0x26, "Apple 1.44M";
+    Pc360K = 0x30, "PC 360K";
+    Pc720K = 0x31, "PC 720K";
+    Pc1M2 = 0x32, "PC 1.2M";
+    Pc1M44 = 0x33, "PC 1.44M";
+    Trs80SsSd = 0x40, "TRS-80 SSSD";
+    Trs80SsDd = 0x41, "TRS-80 SSDD";
+    Trs80DsSd = 0x42, "TRS-80 DSSD";
+    Trs80DsDd = 0x43, "TRS-80 DSDD";
+    Ti994A = 0x50, "TI-99/4A";
+    RolandD20 = 0x60, "Roland D-20";
+    AmstradCpc = 0x70, "Amstrad CPC";
+    Other360K = 0x80, "other 360K";
+    Other1M2 = 0x81, "other 1.2M";
+    Other720K = 0x84, "other 720K";
+    Other1M44 = 0x85, "other 1.44M";
+    TapeGcr1 = 0xe0, "tape GCR1";
+    TapeGcr2 = 0xe1, "tape GCR2";
+    TapeMfm = 0xe2, "tape MFM";
+    HardDriveMfm = 0xf0, "hard drive MFM";
+    HardDriveRll = 0xf1, "hard drive RLL";
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum ScpError {
+    Io(io::Error),
+    BadMagic,
+    TruncatedHeader,
+    UnsupportedBitcellTime(u8),
+    BadChecksum { expected: u32, found: u32 },
+    BadTrackMagic { track: usize, offset: u64 },
+    TruncatedTrack { track: usize, offset: u64 },
+    BadTrackNumber { track: usize, found: u8 },
+    TruncatedFlux { track: usize, rev: usize, offset: u64 },
+    NoTrack { track: usize },
+    BadRevolution { track: usize, rev: usize },
+    BadFooter { offset: u64 },
+    BadTrackSpec { spec: String, reason: String },
+    TooFewRevs { track: usize, found: usize, needed: usize },
+    NoSectors { track: usize },
+    SectorsDoNotFit { track: usize },
+    /// An error reading a particular image file.
+    File { path: PathBuf, source: Box<ScpError> },
+}
+
+impl ScpError {
+    pub(crate) fn in_file(self, path: &Path) -> ScpError {
+        ScpError::File { path: path.to_path_buf(), source: Box::new(self) }
+    }
+}
+
+impl fmt::Display for ScpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScpError::Io(e) => write!(f, "{e}"),
+            ScpError::BadMagic => write!(f, "not an SCP image"),
+            ScpError::TruncatedHeader => write!(f, "truncated image header"),
+            ScpError::UnsupportedBitcellTime(bits) => write!(f, "unsupported bitcell time {bits}"),
+            ScpError::BadChecksum { expected, found } => {
+                write!(f, "checksum mismatch, header has {expected:#010x} but image sums to {found:#010x}")
+            }
+            ScpError::BadTrackMagic { track, offset } => write!(f, "track {track}: missing TRK header at offset {offset:#x}"),
+            ScpError::TruncatedTrack { track, offset } => write!(f, "track {track}: truncated track header at offset {offset:#x}"),
+            ScpError::BadTrackNumber { track, found } => write!(f, "track {track}: track header has track number {found}"),
+            ScpError::TruncatedFlux { track, rev, offset } => {
+                write!(f, "track {track}: truncated flux data for revolution {rev} at offset {offset:#x}")
+            }
+            ScpError::NoTrack { track } => write!(f, "track {track}: not present in image"),
+            ScpError::BadRevolution { track, rev } => write!(f, "track {track}: no revolution {rev}"),
+            ScpError::BadFooter { offset } => write!(f, "bad image footer at offset {offset:#x}"),
+            ScpError::BadTrackSpec { spec, reason } => write!(f, "{spec}: {reason}"),
+            ScpError::TooFewRevs { track, found, needed } => {
+                write!(f, "track {track}: {found} revolutions selected, {needed} needed")
+            }
+            ScpError::NoSectors { track } => write!(f, "track {track}: no sectors found"),
+            ScpError::SectorsDoNotFit { track } => write!(f, "track {track}: sectors do not fit on track"),
+            ScpError::File { path, source } => write!(f, "{}: {source}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for ScpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScpError::Io(e) => Some(e),
+            ScpError::File { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ScpError {
+    fn from(e: io::Error) -> Self {
+        ScpError::Io(e)
+    }
+}
+
+impl From<binrw::Error> for ScpError {
+    fn from(e: binrw::Error) -> Self {
+        match e {
+            binrw::Error::Io(e) => ScpError::Io(e),
+            e => ScpError::Io(io::Error::other(e.to_string())),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// The extract subcommand: copies a subset of tracks and revolutions of an
+// image into a new image.
+
+use clap::Args;
+use std::error::Error;
+use std::fs::File;
+use scpweave::tracks::parse_tracks;
+use scpweave::weave::{write_tracks, TrackSource};
+use scpweave::{ScpImage, ScpWriter};
+
+#[derive(Args, Debug)]
+pub struct ExtractArgs {
+    scp_in: String,
+
+    #[arg(short('o'))]
+    scp_out: String,
+
+    /// Tracks to copy, as cyl.head or #track like the weave's -t
+    #[arg(short('t'), required = true)]
+    tracks: Vec<String>,
+
+    /// Revolutions to copy from each track, e.g. 0,2 [default: all]
+    #[arg(short('r'), value_delimiter = ',')]
+    revs: Vec<usize>,
+}
+
+pub fn run(args: ExtractArgs) -> Result<(), Box<dyn Error>> {
+    let scp = ScpImage::open(&args.scp_in)?;
+    if let Err(e) = scp.verify_checksum() {
+        eprintln!("Warning: {e}");
+    }
+    let rev_count = scp.header.rev_count as usize;
+    let revs = match args.revs.is_empty() {
+        true => (0..rev_count).collect(),
+        false => args.revs,
+    };
+    if let Some(rev) = revs.iter().find(|&&rev| rev >= rev_count) {
+        return Err(format!("Revolution {rev} out of range, {} has {rev_count} revolutions", args.scp_in).into());
+    }
+
+    let mut sources: Vec<Option<TrackSource>> = (0..168).map(|_| None).collect();
+    for spec in &args.tracks {
+        for track in parse_tracks(spec, scp.header.heads)? {
+            if scp.tracks[track].is_some() {
+                sources[track] = Some(TrackSource::Revs(revs.iter().map(|&rev| (0, rev)).collect()));
+            }
+        }
+    }
+    if sources.iter().all(Option::is_none) {
+        return Err(format!("None of the tracks given are present in {}", args.scp_in).into());
+    }
+
+    let mut header = scp.header;
+    header.rev_count = revs.len() as u8;
+    let mut writer = ScpWriter::new(File::create(&args.scp_out)?, header)?;
+    write_tracks(std::slice::from_ref(&scp), &sources, &mut writer)?;
+    if let Some(footer) = scp.footer.clone() {
+        crate::write_footer(&mut writer, footer)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
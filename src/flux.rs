@@ -0,0 +1,172 @@
+//! Flux decoding: SCP flux words to transition intervals, and a PLL turning
+//! intervals into a bitcell stream.
+
+use crate::{ScpError, ScpImage};
+
+/// Converts flux samples `sample_bits` wide to transition intervals in ns,
+/// adding in overflow words.
+pub fn flux_intervals(flux: &[u16], sample_bits: u32, sample_ns: f64) -> Vec<f64> {
+    let mut intervals = Vec::with_capacity(flux.len());
+    let mut overflow = 0u64;
+    for &value in flux {
+        if value == 0 {
+            overflow += 1 << sample_bits;
+            continue;
+        }
+        intervals.push((overflow + value as u64) as f64 * sample_ns);
+        overflow = 0;
+    }
+    intervals
+}
+
+/// Transition intervals in ns of a revolution, at the image's resolution.
+pub fn revolution_intervals(scp: &ScpImage, track: usize, rev: usize) -> Result<Vec<f64>, ScpError> {
+    Ok(flux_intervals(&scp.revolution_flux(track, rev)?, scp.sample_bits(), scp.sample_ns()))
+}
+
+/// Converts flux samples between 8 and 16 bit widths and between sample
+/// periods, re-expressing overflows. Transition times are rounded against the
+/// running total so resampling error does not accumulate.
+pub fn convert_flux(flux: &[u16], from_bits: u32, from_ns: f64, to_bits: u32, to_ns: f64) -> Vec<u16> {
+    if from_bits == to_bits && from_ns == to_ns {
+        return flux.to_vec();
+    }
+    let scale = from_ns / to_ns;
+    let mut converted = Vec::with_capacity(flux.len());
+    let mut overflow = 0u64;
+    let mut time = 0u64;
+    let mut converted_time = 0u64;
+    for &value in flux {
+        if value == 0 {
+            overflow += 1 << from_bits;
+            continue;
+        }
+        time += overflow + value as u64;
+        overflow = 0;
+        let mut value = ((time as f64 * scale).round() as u64).saturating_sub(converted_time).max(1);
+        // an exact multiple of the overflow can't end in a zero word, so take a
+        // tick more and let the next interval make up for it
+        if value & ((1 << to_bits) - 1) == 0 {
+            value += 1;
+        }
+        converted_time += value;
+        while value >> to_bits != 0 {
+            converted.push(0);
+            value -= 1 << to_bits;
+        }
+        converted.push(value as u16);
+    }
+    converted
+}
+
+/// Settings of the PLL used by `pll_decode`.
+#[derive(Debug, Copy, Clone)]
+pub struct PllConfig {
+    /// Nominal bitcell period in ns
+    pub cell_ns: f64,
+    /// Fraction of the phase error of a transition applied to the clock period
+    pub period_adjust: f64,
+    /// Fraction of the phase error of a transition corrected immediately
+    pub phase_adjust: f64,
+    /// Largest deviation of the clock period from nominal, as a fraction
+    pub tolerance: f64,
+}
+
+impl PllConfig {
+    /// The default settings for a nominal bitcell period in ns.
+    pub fn new(cell_ns: f64) -> PllConfig {
+        PllConfig { cell_ns, period_adjust: 0.05, phase_adjust: 0.6, tolerance: 0.1 }
+    }
+}
+
+/// Turns transition intervals in ns into a bitcell stream, one `true` per
+/// transition.
+pub fn pll_decode(intervals: &[f64], pll: &PllConfig) -> Vec<bool> {
+    let cell_ns = pll.cell_ns;
+    let clock_min = cell_ns * (1.0 - pll.tolerance);
+    let clock_max = cell_ns * (1.0 + pll.tolerance);
+    let mut clock = cell_ns;
+    let mut ticks = 0.0;
+    let mut bits = Vec::with_capacity(intervals.len() * 3);
+    for &interval in intervals {
+        ticks += interval;
+        if ticks < clock / 2.0 {
+            continue;
+        }
+        let mut zeros = 0;
+        loop {
+            ticks -= clock;
+            if ticks < clock / 2.0 {
+                break;
+            }
+            zeros += 1;
+            bits.push(false);
+        }
+        bits.push(true);
+        // ticks is now the phase error of this transition. After a long run of
+        // zeros it says little about the clock, so drift back to nominal.
+        if zeros <= 3 {
+            clock += ticks * pll.period_adjust;
+        } else {
+            clock += (cell_ns - clock) * pll.period_adjust;
+        }
+        clock = clock.clamp(clock_min, clock_max);
+        ticks *= 1.0 - pll.phase_adjust;
+    }
+    bits
+}
+
+/// Decodes one revolution of a track to a bitcell stream.
+pub fn decode_revolution(scp: &ScpImage, track: usize, rev: usize, pll: &PllConfig) -> Result<Vec<bool>, ScpError> {
+    Ok(pll_decode(&revolution_intervals(scp, track, rev)?, pll))
+}
+
+/// Inverse of `pll_decode`: lays out a bitcell stream at a fixed cell period
+/// and encodes it as SCP flux words, returning them with their duration in
+/// samples.
+pub fn encode_flux(bits: &[bool], cell_ns: f64, sample_ns: f64) -> (Vec<u16>, u32) {
+    let mut flux = Vec::new();
+    let mut time = 0.0;
+    let mut last = 0.0;
+    for &bit in bits {
+        time += cell_ns / sample_ns;
+        if bit {
+            let mut interval = (time - last).round() as u32;
+            last += interval as f64;
+            while interval >= 0x10000 {
+                flux.push(0);
+                interval -= 0x10000;
+            }
+            flux.push(interval.max(1) as u16);
+        }
+    }
+    (flux, time.round() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total_ns(flux: &[u16], sample_bits: u32, sample_ns: f64) -> f64 {
+        flux_intervals(flux, sample_bits, sample_ns).iter().sum()
+    }
+
+    #[test]
+    fn convert_preserves_time() {
+        let flux: Vec<u16> = (0..1000).map(|i| [79, 80, 121, 160, 300, 0, 1234][i % 7]).collect();
+        let total = total_ns(&flux, 16, 25.0);
+        for (bits, ns) in [(8, 25.0), (16, 50.0), (8, 50.0)] {
+            let converted = convert_flux(&flux, 16, 25.0, bits, ns);
+            assert!((total_ns(&converted, bits, ns) - total).abs() <= ns / 2.0);
+            let back = convert_flux(&converted, bits, ns, 16, 25.0);
+            assert!((total_ns(&back, 16, 25.0) - total).abs() <= ns / 2.0);
+        }
+    }
+
+    #[test]
+    fn convert_overflows() {
+        assert_eq!(convert_flux(&[300], 16, 25.0, 8, 25.0), vec![0, 44]);
+        assert_eq!(convert_flux(&[0, 44], 8, 25.0, 16, 50.0), vec![150]);
+        assert_eq!(convert_flux(&[256; 4], 16, 25.0, 8, 25.0), vec![0, 1, 255, 0, 1, 255]);
+    }
+}
@@ -0,0 +1,167 @@
+use binrw::{binrw, BinRead, BinWrite};
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+use crate::ScpError;
+
+pub(crate) const FOOTER_LEN: u64 = 0x30;
+
+#[binrw]
+#[brw(little)]
+struct RawFooter {
+    manufacturer: u32,
+    model: u32,
+    serial: u32,
+    creator: u32,
+    application_name: u32,
+    comments: u32,
+    creation_time: u64,
+    modification_time: u64,
+    application_version: u8,
+    hardware_version: u8,
+    firmware_version: u8,
+    format_revision: u8,
+    #[brw(magic = b"FPCS")]
+    end: (),
+}
+
+/// The extension footer present when the header's footer flag is set.
+/// Timestamps are seconds since the Unix epoch; versions are BCD-like bytes
+/// with the major version in the high nibble.
+#[derive(Debug, Clone, Default)]
+pub struct ScpFooter {
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub creator: Option<String>,
+    pub application_name: Option<String>,
+    pub comments: Option<String>,
+    pub creation_time: u64,
+    pub modification_time: u64,
+    pub application_version: u8,
+    pub hardware_version: u8,
+    pub firmware_version: u8,
+    pub format_revision: u8,
+}
+
+fn read_string<R: Read + Seek>(file: &mut R, offset: u32) -> Result<Option<String>, ScpError> {
+    if offset == 0 {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(offset as u64))?;
+    let mut len = [0; 2];
+    file.read_exact(&mut len)?;
+    let mut data = vec![0; u16::from_le_bytes(len) as usize];
+    file.read_exact(&mut data)?;
+    Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+}
+
+impl ScpFooter {
+    pub(crate) fn read<R: Read + Seek>(file: &mut R) -> Result<ScpFooter, ScpError> {
+        let offset = file.seek(SeekFrom::End(-(FOOTER_LEN as i64)))
+            .map_err(|_| ScpError::BadFooter { offset: 0 })?;
+        let raw = RawFooter::read(file).map_err(|_| ScpError::BadFooter { offset })?;
+        let mut string = |offset| read_string(file, offset).map_err(|_| ScpError::BadFooter { offset: offset as u64 });
+        Ok(ScpFooter {
+            manufacturer: string(raw.manufacturer)?,
+            model: string(raw.model)?,
+            serial: string(raw.serial)?,
+            creator: string(raw.creator)?,
+            application_name: string(raw.application_name)?,
+            comments: string(raw.comments)?,
+            creation_time: raw.creation_time,
+            modification_time: raw.modification_time,
+            application_version: raw.application_version,
+            hardware_version: raw.hardware_version,
+            firmware_version: raw.firmware_version,
+            format_revision: raw.format_revision,
+        })
+    }
+
+    /// Serializes the footer with its strings, to be placed at `offset` at the
+    /// end of an image.
+    pub(crate) fn to_bytes(&self, offset: u32) -> Result<Vec<u8>, ScpError> {
+        let mut data = Cursor::new(Vec::new());
+        let mut string = |s: &Option<String>| -> Result<u32, ScpError> {
+            let Some(s) = s else { return Ok(0) };
+            let pos = offset + data.position() as u32;
+            // the length is 16 bits, so cut longer strings at a character boundary
+            let mut len = s.len().min(0xffff);
+            while !s.is_char_boundary(len) {
+                len -= 1;
+            }
+            (len as u16).write_le(&mut data)?;
+            data.write_all(&s.as_bytes()[..len])?;
+            data.write_all(&[0])?;
+            Ok(pos)
+        };
+        let raw = RawFooter {
+            manufacturer: string(&self.manufacturer)?,
+            model: string(&self.model)?,
+            serial: string(&self.serial)?,
+            creator: string(&self.creator)?,
+            application_name: string(&self.application_name)?,
+            comments: string(&self.comments)?,
+            creation_time: self.creation_time,
+            modification_time: self.modification_time,
+            application_version: self.application_version,
+            hardware_version: self.hardware_version,
+            firmware_version: self.firmware_version,
+            format_revision: self.format_revision,
+            end: (),
+        };
+        raw.write(&mut data)?;
+        Ok(data.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(footer: &ScpFooter) -> (Vec<u8>, ScpFooter) {
+        let mut image = vec![0; 0x100];
+        image.extend(footer.to_bytes(0x100).unwrap());
+        let read = ScpFooter::read(&mut Cursor::new(&image)).unwrap();
+        (image, read)
+    }
+
+    #[test]
+    fn strings_and_fields() {
+        let footer = ScpFooter {
+            manufacturer: Some("Acme".into()),
+            comments: Some("disk 7".into()),
+            creation_time: 1_700_000_000,
+            modification_time: 1_700_000_100,
+            application_version: 0x12,
+            format_revision: 0x22,
+            ..Default::default()
+        };
+        let (image, read) = round_trip(&footer);
+        assert!(image.ends_with(b"FPCS"));
+        let raw = &image[image.len() - FOOTER_LEN as usize..];
+        // manufacturer is written first, straight after the image data, as a
+        // length, the string and a terminating zero
+        assert_eq!(raw[0..4], 0x100u32.to_le_bytes());
+        assert_eq!(image[0x100..0x107], *b"\x04\0Acme\0");
+        assert_eq!(raw[4..8], [0; 4]);
+        assert_eq!(raw[20..24], 0x107u32.to_le_bytes());
+        assert_eq!(read.manufacturer.as_deref(), Some("Acme"));
+        assert_eq!(read.model, None);
+        assert_eq!(read.comments.as_deref(), Some("disk 7"));
+        assert_eq!((read.creation_time, read.modification_time), (1_700_000_000, 1_700_000_100));
+        assert_eq!((read.application_version, read.format_revision), (0x12, 0x22));
+    }
+
+    #[test]
+    fn long_strings_truncated() {
+        let footer = ScpFooter {
+            model: Some("x".repeat(0x10005)),
+            comments: Some("é".repeat(40000)),
+            ..Default::default()
+        };
+        let (_, read) = round_trip(&footer);
+        assert_eq!(read.model.unwrap(), "x".repeat(0xffff));
+        // 0xffff bytes would split the last two byte character
+        assert_eq!(read.comments.unwrap(), "é".repeat(0x7fff));
+    }
+}
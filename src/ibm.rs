@@ -0,0 +1,356 @@
+//! IBM System/34 (MFM) and System/3740 (FM) sector format.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The sector encoding of a track.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encoding {
+    Fm,
+    Mfm,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fm" => Ok(Encoding::Fm),
+            "mfm" => Ok(Encoding::Mfm),
+            _ => Err(format!("unknown encoding {s}, expected fm or mfm")),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Encoding::Fm => write!(f, "FM"),
+            Encoding::Mfm => write!(f, "MFM"),
+        }
+    }
+}
+
+/// A sector found on a track, from its ID field and the data field after it.
+/// `data` excludes the CRC, which is kept in `data_crc`.
+#[derive(Debug, Clone)]
+pub struct Sector {
+    pub cyl: u8,
+    pub head: u8,
+    pub record: u8,
+    pub size: u8,
+    pub id_ok: bool,
+    pub deleted: bool,
+    pub data: Option<Vec<u8>>,
+    pub data_crc: u16,
+    pub data_ok: bool,
+    /// Bitcell offset of the ID address mark within the revolution
+    pub position: usize,
+}
+
+/// Whether a sector was read intact, or what went wrong.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SectorStatus {
+    Ok,
+    BadIdCrc,
+    /// ID field found but no data field following it
+    NoData,
+    BadDataCrc,
+}
+
+impl fmt::Display for SectorStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SectorStatus::Ok => write!(f, "ok"),
+            SectorStatus::BadIdCrc => write!(f, "bad ID CRC"),
+            SectorStatus::NoData => write!(f, "no data"),
+            SectorStatus::BadDataCrc => write!(f, "bad data CRC"),
+        }
+    }
+}
+
+impl Sector {
+    /// The first problem found with the sector, checking the ID field first.
+    pub fn status(&self) -> SectorStatus {
+        if !self.id_ok {
+            SectorStatus::BadIdCrc
+        } else if self.data.is_none() {
+            SectorStatus::NoData
+        } else if !self.data_ok {
+            SectorStatus::BadDataCrc
+        } else {
+            SectorStatus::Ok
+        }
+    }
+}
+
+// Data marks run from F8 to FB, with F8 and F9 marking deleted data. FM
+// encodes each mark with a C7 clock pattern.
+const IDAM: u8 = 0xfe;
+const DAM: u8 = 0xfb;
+const DDAM: u8 = 0xf8;
+
+// bitcells allowed between the ID field and its data mark
+const DAM_WINDOW: usize = 64 * 16;
+
+/// Updates a CRC-CCITT (polynomial 0x1021) with `data`. Sector CRCs start
+/// from 0xffff and cover the sync bytes and address mark.
+pub fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn read_byte(bits: &[bool], pos: usize) -> Option<u8> {
+    let cells = bits.get(pos..pos + 16)?;
+    Some(cells.chunks(2).fold(0, |byte, cell| (byte << 1) | cell[1] as u8))
+}
+
+fn read_bytes(bits: &[bool], pos: usize, count: usize) -> Option<Vec<u8>> {
+    (0..count).map(|i| read_byte(bits, pos + i * 16)).collect()
+}
+
+// Finds address marks, returning the mark byte and the bitcell position of the
+// byte following it.
+fn find_marks(bits: &[bool], encoding: Encoding) -> Vec<(u8, usize)> {
+    let mut marks = Vec::new();
+    let mut shift: u64 = 0;
+    for (i, &bit) in bits.iter().enumerate() {
+        shift = (shift << 1) | bit as u64;
+        match encoding {
+            Encoding::Mfm => {
+                if shift & 0xffff_ffff_ffff == 0x4489_4489_4489 {
+                    if let Some(mark) = read_byte(bits, i + 1) {
+                        marks.push((mark, i + 17));
+                    }
+                }
+            }
+            Encoding::Fm => {
+                let mark = match shift & 0xffff_ffff {
+                    0xaaaa_f57e => IDAM,
+                    0xaaaa_f56f => DAM,
+                    0xaaaa_f56e => 0xfa,
+                    0xaaaa_f56b => 0xf9,
+                    0xaaaa_f56a => DDAM,
+                    _ => continue,
+                };
+                marks.push((mark, i + 1));
+            }
+        }
+    }
+    marks
+}
+
+/// Decodes the sectors in the bitcells of one revolution (see
+/// `flux::decode_revolution`), in the order found.
+pub fn decode_sectors(bits: &[bool], encoding: Encoding) -> Vec<Sector> {
+    let prefix: &[u8] = match encoding {
+        Encoding::Mfm => &[0xa1, 0xa1, 0xa1],
+        Encoding::Fm => &[],
+    };
+    let mut sectors: Vec<Sector> = Vec::new();
+    let mut last_id: Option<usize> = None;
+    for (mark, pos) in find_marks(bits, encoding) {
+        match mark {
+            IDAM => {
+                let Some(id) = read_bytes(bits, pos, 6) else { break };
+                let crc = crc16(crc16(crc16(0xffff, prefix), &[mark]), &id);
+                sectors.push(Sector {
+                    cyl: id[0],
+                    head: id[1],
+                    record: id[2],
+                    size: id[3],
+                    id_ok: crc == 0,
+                    deleted: false,
+                    data: None,
+                    data_crc: 0,
+                    data_ok: false,
+                    position: pos,
+                });
+                last_id = Some(pos);
+            }
+            DDAM..=DAM => {
+                let Some(id_pos) = last_id.take() else { continue };
+                if pos - id_pos > DAM_WINDOW {
+                    continue;
+                }
+                let sector = sectors.last_mut().unwrap();
+                let len = 128usize << (sector.size & 7);
+                let Some(data) = read_bytes(bits, pos, len + 2) else { break };
+                let crc = crc16(crc16(crc16(0xffff, prefix), &[mark]), &data);
+                sector.deleted = mark < 0xfa;
+                sector.data_crc = u16::from_be_bytes([data[len], data[len + 1]]);
+                sector.data = Some(data[..len].to_vec());
+                sector.data_ok = crc == 0;
+            }
+            _ => {}
+        }
+    }
+    sectors
+}
+
+struct Encoder {
+    encoding: Encoding,
+    bits: Vec<bool>,
+}
+
+impl Encoder {
+    fn raw(&mut self, word: u16) {
+        self.bits.extend((0..16).rev().map(|i| word >> i & 1 != 0));
+    }
+
+    fn byte_with_clock(&mut self, byte: u8, clock: u8) {
+        for i in (0..8).rev() {
+            let data = byte >> i & 1 != 0;
+            let clock = match self.encoding {
+                Encoding::Fm => clock >> i & 1 != 0,
+                Encoding::Mfm => !data && !self.bits.last().copied().unwrap_or(false),
+            };
+            self.bits.push(clock);
+            self.bits.push(data);
+        }
+    }
+
+    fn bytes(&mut self, byte: u8, count: usize) {
+        for _ in 0..count {
+            self.byte_with_clock(byte, 0xff);
+        }
+    }
+
+    fn data(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.byte_with_clock(byte, 0xff);
+        }
+    }
+
+    fn mark(&mut self, mark: u8) {
+        match self.encoding {
+            Encoding::Mfm => {
+                for _ in 0..3 {
+                    self.raw(if mark == 0xfc { 0x5224 } else { 0x4489 });
+                }
+                self.byte_with_clock(mark, 0xff);
+            }
+            Encoding::Fm => self.byte_with_clock(mark, if mark == 0xfc { 0xd7 } else { 0xc7 }),
+        }
+    }
+}
+
+struct Gaps {
+    fill: u8,
+    sync: usize,
+    gap4a: usize,
+    gap1: usize,
+    gap2: usize,
+    gap3: usize,
+}
+
+const MFM_GAPS: Gaps = Gaps { fill: 0x4e, sync: 12, gap4a: 80, gap1: 50, gap2: 22, gap3: 84 };
+const FM_GAPS: Gaps = Gaps { fill: 0xff, sync: 6, gap4a: 40, gap1: 26, gap2: 11, gap3: 27 };
+
+/// Lays out a fresh track of `track_cells` bitcells holding the given sectors
+/// in order, shrinking gap 3 if needed to fit. Returns `None` if they do not
+/// fit at all.
+pub fn encode_track(encoding: Encoding, sectors: &[&Sector], track_cells: usize) -> Option<Vec<bool>> {
+    let gaps = match encoding {
+        Encoding::Mfm => MFM_GAPS,
+        Encoding::Fm => FM_GAPS,
+    };
+    let mark_len = match encoding {
+        Encoding::Mfm => 4,
+        Encoding::Fm => 1,
+    };
+    let track_bytes = track_cells / 16;
+    let header = gaps.gap4a + gaps.sync + mark_len + gaps.gap1;
+    let sector_bytes: usize = sectors.iter()
+        .map(|s| gaps.sync * 2 + mark_len * 2 + 6 + gaps.gap2 + s.data.as_ref().unwrap().len() + 2)
+        .sum();
+    let free = track_bytes.checked_sub(header + sector_bytes)?;
+    let gap3 = gaps.gap3.min(free / sectors.len().max(1));
+    if gap3 == 0 {
+        return None;
+    }
+
+    let mut enc = Encoder { encoding, bits: Vec::with_capacity(track_cells) };
+    let prefix: &[u8] = match encoding {
+        Encoding::Mfm => &[0xa1, 0xa1, 0xa1],
+        Encoding::Fm => &[],
+    };
+    enc.bytes(gaps.fill, gaps.gap4a);
+    enc.bytes(0, gaps.sync);
+    enc.mark(0xfc);
+    enc.bytes(gaps.fill, gaps.gap1);
+    for sector in sectors {
+        let id = [sector.cyl, sector.head, sector.record, sector.size];
+        enc.bytes(0, gaps.sync);
+        enc.mark(IDAM);
+        enc.data(&id);
+        enc.data(&crc16(crc16(crc16(0xffff, prefix), &[IDAM]), &id).to_be_bytes());
+        enc.bytes(gaps.fill, gaps.gap2);
+        enc.bytes(0, gaps.sync);
+        let mark = if sector.deleted { DDAM } else { DAM };
+        let data = sector.data.as_ref().unwrap();
+        enc.mark(mark);
+        enc.data(data);
+        if sector.data_ok {
+            enc.data(&crc16(crc16(crc16(0xffff, prefix), &[mark]), data).to_be_bytes());
+        } else {
+            enc.data(&sector.data_crc.to_be_bytes());
+        }
+        enc.bytes(gaps.fill, gap3);
+    }
+    while enc.bits.len() + 16 <= track_cells {
+        enc.bytes(gaps.fill, 1);
+    }
+    Some(enc.bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flux::{encode_flux, flux_intervals, pll_decode, PllConfig};
+
+    fn sector(record: u8, deleted: bool) -> Sector {
+        Sector {
+            cyl: 3,
+            head: 1,
+            record,
+            size: 2,
+            id_ok: true,
+            deleted,
+            data: Some((0..512).map(|i| (i * 7 + record as usize) as u8).collect()),
+            data_crc: 0,
+            data_ok: true,
+            position: 0,
+        }
+    }
+
+    fn round_trip(encoding: Encoding, cell_ns: f64) {
+        let sectors: Vec<_> = (1..=4).map(|record| sector(record, record == 2)).collect();
+        let refs: Vec<_> = sectors.iter().collect();
+        let bits = encode_track(encoding, &refs, (200e6 / cell_ns) as usize).unwrap();
+        let (flux, _) = encode_flux(&bits, cell_ns, 25.0);
+        let decoded = decode_sectors(&pll_decode(&flux_intervals(&flux, 16, 25.0), &PllConfig::new(cell_ns)), encoding);
+        assert_eq!(decoded.len(), sectors.len());
+        for (decoded, sector) in decoded.iter().zip(&sectors) {
+            assert_eq!((decoded.cyl, decoded.head, decoded.record, decoded.size), (3, 1, sector.record, 2));
+            assert_eq!(decoded.status(), SectorStatus::Ok);
+            assert_eq!(decoded.deleted, sector.deleted);
+            assert_eq!(decoded.data, sector.data);
+        }
+    }
+
+    #[test]
+    fn mfm_round_trip() {
+        round_trip(Encoding::Mfm, 2000.0);
+    }
+
+    #[test]
+    fn fm_round_trip() {
+        round_trip(Encoding::Fm, 4000.0);
+    }
+}
@@ -0,0 +1,138 @@
+// The info subcommand: prints the header, footer and track table of an image.
+
+use clap::Args;
+use serde_json::{json, Value};
+use std::error::Error;
+use scpweave::{ScpFooter, ScpImage};
+
+#[derive(Args, Debug)]
+pub struct InfoArgs {
+    scp: String,
+
+    /// Print as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+fn version(version: u8) -> String {
+    format!("{}.{}", version >> 4, version & 0xf)
+}
+
+fn footer_json(footer: &ScpFooter) -> Value {
+    json!({
+        "manufacturer": footer.manufacturer,
+        "model": footer.model,
+        "serial": footer.serial,
+        "creator": footer.creator,
+        "application_name": footer.application_name,
+        "comments": footer.comments,
+        "creation_time": footer.creation_time,
+        "modification_time": footer.modification_time,
+        "application_version": version(footer.application_version),
+        "hardware_version": version(footer.hardware_version),
+        "firmware_version": version(footer.firmware_version),
+        "format_revision": version(footer.format_revision),
+    })
+}
+
+pub fn run(args: InfoArgs) -> Result<(), Box<dyn Error>> {
+    let scp = ScpImage::open(&args.scp)?;
+    let header = &scp.header;
+    let computed = scp.compute_checksum()?;
+    let flags: Vec<_> = header.flags.iter_names().map(|(name, _)| name).collect();
+    let heads = match header.heads {
+        0 => "both",
+        1 => "side 0",
+        2 => "side 1",
+        _ => "unknown",
+    };
+    // each revolution as (track, rev, duration, rpm, num_bitcells, offset)
+    let revs: Vec<_> = scp.tracks.iter().enumerate()
+        .filter_map(|(i, track)| Some((i, track.as_ref()?)))
+        .flat_map(|(i, track)| track.revs.iter().enumerate().map(move |(rev, scp_rev)| (i, rev, scp_rev)))
+        .map(|(i, rev, scp_rev)| {
+            let rpm = 60e9 / (scp_rev.duration as f64 * scp.sample_ns());
+            (i, rev, scp_rev.duration, rpm, scp_rev.num_bitcells, header.track_data_headers[i] as u64 + scp_rev.offset as u64)
+        })
+        .collect();
+
+    if args.json {
+        let tracks: Vec<_> = scp.tracks.iter().enumerate()
+            .filter(|(_, track)| track.is_some())
+            .map(|(i, _)| json!({
+                "track": i,
+                "cyl": i / 2,
+                "head": i % 2,
+                "offset": header.track_data_headers[i],
+                "revs": revs.iter().filter(|r| r.0 == i).map(|&(_, _, duration, rpm, num_bitcells, offset)| json!({
+                    "duration": duration,
+                    "rpm": rpm,
+                    "num_bitcells": num_bitcells,
+                    "offset": offset,
+                })).collect::<Vec<_>>(),
+            }))
+            .collect();
+        let info = json!({
+            "file": args.scp,
+            "version": version(header.version),
+            "disk_type": header.disk_type.to_string(),
+            "disk_type_code": u8::from(header.disk_type),
+            "rev_count": header.rev_count,
+            "start_track": header.start_track,
+            "end_track": header.end_track,
+            "flags": flags,
+            "bitcell_time": header.bitcell_time,
+            "heads": header.heads,
+            "resolution": header.resolution,
+            "sample_ns": scp.sample_ns(),
+            "checksum": header.checksum,
+            "checksum_ok": computed == header.checksum,
+            "footer": scp.footer.as_ref().map(footer_json),
+            "tracks": tracks,
+        });
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    println!("File:          {}", args.scp);
+    println!("Version:       {}", version(header.version));
+    println!("Disk type:     {} ({:#04x})", header.disk_type, u8::from(header.disk_type));
+    println!("Revolutions:   {}", header.rev_count);
+    println!("Tracks:        {}-{}", header.start_track, header.end_track);
+    println!("Flags:         {}", flags.join(" | "));
+    println!("Bitcell width: {} bits", scp.sample_bits());
+    println!("Heads:         {heads}");
+    println!("Resolution:    {} ({}ns)", header.resolution, scp.sample_ns());
+    if computed == header.checksum {
+        println!("Checksum:      {:#010x} (ok)", header.checksum);
+    } else {
+        println!("Checksum:      {:#010x} (bad, computed {computed:#010x})", header.checksum);
+    }
+    if let Some(footer) = &scp.footer {
+        let strings = [
+            ("Manufacturer", &footer.manufacturer),
+            ("Model", &footer.model),
+            ("Serial", &footer.serial),
+            ("Creator", &footer.creator),
+            ("Application", &footer.application_name),
+            ("Comments", &footer.comments),
+        ];
+        for (name, value) in strings {
+            if let Some(value) = value {
+                println!("{:15}{value}", format!("{name}:"));
+            }
+        }
+        println!("Created:       {}", footer.creation_time);
+        println!("Modified:      {}", footer.modification_time);
+        println!("Versions:      application {}, hardware {}, firmware {}, format {}",
+                 version(footer.application_version), version(footer.hardware_version),
+                 version(footer.firmware_version), version(footer.format_revision));
+    }
+    println!();
+    println!("Track  Cyl.Head  Rev  Duration     RPM  Bitcells    Offset");
+    for (i, rev, duration, rpm, num_bitcells, offset) in revs {
+        println!("{i:5}  {:>8}  {rev:3}  {duration:8}  {rpm:6.2}  {num_bitcells:8}  {offset:#08x}",
+                 format!("{}.{}", i / 2, i % 2));
+    }
+    Ok(())
+}
@@ -0,0 +1,18 @@
+pub mod amiga;
+pub mod c64;
+mod disk_type;
+mod error;
+pub mod flux;
+mod footer;
+pub mod ibm;
+mod scp;
+pub mod tracks;
+pub mod verify;
+pub mod weave;
+mod writer;
+
+pub use disk_type::DiskType;
+pub use error::ScpError;
+pub use footer::ScpFooter;
+pub use scp::{checksum, ScpFlags, ScpHeader, ScpImage, ScpRev, ScpTrack};
+pub use writer::ScpWriter;
@@ -1,142 +1,372 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::error::Error;
 use std::fs::File;
-use std::io::prelude::*;
-use std::io::Cursor;
-use std::io::SeekFrom;
+use std::io::{Seek, Write};
 use std::process::exit;
-use std::usize;
-use binrw::{binrw, BinRead, BinWrite};
+use std::time::{SystemTime, UNIX_EPOCH};
+use scpweave::weave::{auto_select, reconcile_revs, weave_sectors, write_tracks, RevPolicy, SectorCopy, TrackSource};
+use scpweave::ibm::Encoding;
+use scpweave::tracks::parse_tracks;
+use scpweave::{ScpFlags, ScpFooter, ScpImage, ScpWriter};
+
+mod diff;
+mod extract;
+mod info;
+mod plan;
+mod sectors;
+mod verify_cmd;
 
 #[derive(Parser, Debug)]
-#[command()]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    weave: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the header, footer and track table of an image
+    Info(info::InfoArgs),
+    /// Decode IBM MFM/FM, Amiga or 1541 GCR sectors and report their status per revolution
+    Sectors(sectors::SectorsArgs),
+    /// Compare two images track by track
+    Diff(diff::DiffArgs),
+    /// Copy some tracks of an image into a new image
+    Extract(extract::ExtractArgs),
+    /// Check the structure of images, failing if any problems are found
+    Verify(verify_cmd::VerifyArgs),
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
     #[arg()]
     scp_in: Vec<String>,
 
     #[arg(short('o'))]
-    scp_out: String,
+    scp_out: Option<String>,
+
+    /// Read inputs, track sources and header overrides from a TOML or JSON
+    /// plan file
+    #[arg(long)]
+    plan: Option<String>,
 
+    /// Take tracks from an input: cyl.head=source, where cyl and head may be
+    /// ranges like 0-39 or *, or #track=source for raw SCP track numbers
     #[arg(short('t'))]
     tracks: Vec<String>,
-}
 
-#[binrw]
-#[brw(little, magic=b"SCP")]
-#[derive(Debug, Copy, Clone)]
-struct ScpHeader {
-    version: u8,
-    disk_type: u8,
-    rev_count: u8,
-    start_track: u8,
-    end_track: u8,
-    flags: u8,
-    bitcell_time: u8,
-    heads: u8,
-    resolution: u8,
-    checksum: u32,
-    track_data_headers: [u32; 168],
+    /// Take individual revolutions: tracks=source/rev,source/rev,...
+    #[arg(short('r'))]
+    revs: Vec<String>,
+
+    /// Rebuild tracks from the best copy of each IBM MFM/FM sector in any input
+    #[arg(short('s'))]
+    sectors: Vec<String>,
+
+    #[arg(long)]
+    auto: bool,
+
+    /// Decode sectors of some tracks as FM or MFM instead of detecting the
+    /// encoding: tracks=fm or tracks=mfm
+    #[arg(short('e'), long)]
+    encoding: Vec<String>,
+
+    /// Source for tracks not chosen otherwise, falling back to the first input
+    /// holding the track [default: 0]
+    #[arg(long)]
+    default_source: Option<usize>,
+
+    #[arg(long)]
+    ignore_checksum: bool,
+
+    /// Output sample period, 25ns * (resolution + 1) [default: finest input]
+    #[arg(long)]
+    resolution: Option<u8>,
+
+    /// How to handle tracks with differing revolution counts: min, truncate or
+    /// pad [default: min]. min refuses to drop revolutions picked with -r
+    #[arg(long)]
+    rev_policy: Option<RevPolicy>,
+
+    /// Revolutions per track for --rev-policy truncate or pad
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..))]
+    rev_count: Option<u8>,
+
+    /// Weave inputs captured at different speeds, track densities or disk types
+    #[arg(long)]
+    force: bool,
+
+    /// Disk type code to write in the output header
+    #[arg(long)]
+    disk_type: Option<u8>,
+
+    /// Write an extended mode image, with the track offset table at 0x80.
+    /// Without it a standard image is written, even from extended inputs
+    #[arg(long)]
+    extended: bool,
+
+    /// Footer fields to set, adding a footer if no input has one
+    #[arg(long)]
+    manufacturer: Option<String>,
+
+    #[arg(long)]
+    model: Option<String>,
+
+    #[arg(long)]
+    serial: Option<String>,
+
+    #[arg(long)]
+    creator: Option<String>,
+
+    #[arg(long)]
+    comments: Option<String>,
 }
 
-#[binrw]
-#[brw(little, magic=b"TRK", import(rev_count: u8))]
-#[derive(Debug, Clone)]
-struct ScpTrack {
-    track_number: u8,
-    #[br(count=rev_count)]
-    revs: Vec<ScpRev>,
+// Parses tracks=fm or tracks=mfm parameters into the encoding of each track.
+fn parse_encodings(params: &[String], heads: u8) -> Result<Vec<Option<Encoding>>, Box<dyn Error>> {
+    let mut encodings = vec![None; 168];
+    for param in params {
+        let (spec, encoding) = param.split_once('=').ok_or(format!("{param}: expected tracks=fm or tracks=mfm"))?;
+        let encoding: Encoding = encoding.parse().map_err(|e| format!("{param}: {e}"))?;
+        for track in parse_tracks(spec, heads)? {
+            encodings[track] = Some(encoding);
+        }
+    }
+    Ok(encodings)
 }
 
-#[binrw]
-#[brw(little)]
-#[derive(Debug, Copy, Clone)]
-struct ScpRev {
-    duration: u32,
-    num_bitcells: u32,
-    offset: u32,
+// The heads value to check track specs naming no single input against: the
+// inputs' own if they all hold the same side, otherwise both sides.
+fn combined_heads(inputs: &[ScpImage]) -> u8 {
+    let heads = inputs[0].header.heads;
+    match inputs.iter().all(|scp| scp.header.heads == heads) {
+        true => heads,
+        false => 0,
+    }
 }
 
-struct Scp {
-    file: File,
-    header: ScpHeader,
-    tracks: Vec<Option<ScpTrack>>,
+fn parse_source(param: &str, source: &str, inputs: usize) -> Result<usize, String> {
+    let source: usize = source.parse().map_err(|e| format!("{param}: {e}"))?;
+    if source >= inputs {
+        return Err(format!("{param}: source {source} out of range, {inputs} input files specified"));
+    }
+    Ok(source)
 }
 
-fn checksum(data: &[u8]) -> u32 {
-    let mut sum: u32 = 0;
-    for &byte in data {
-        sum = sum.wrapping_add(byte as u32);
+fn check_compatible(a: &ScpImage, b: &ScpImage) -> Result<(), String> {
+    let (a_name, b_name) = (a.path().display(), b.path().display());
+    let (a, b) = (&a.header, &b.header);
+    if a.rpm() != b.rpm() {
+        return Err(format!("{a_name} is a {} RPM capture but {b_name} is {} RPM", a.rpm(), b.rpm()));
+    }
+    if a.tpi() != b.tpi() {
+        return Err(format!("{a_name} is a {} TPI capture but {b_name} is {} TPI", a.tpi(), b.tpi()));
+    }
+    if a.disk_type != b.disk_type {
+        return Err(format!("{a_name} is a {} disk but {b_name} is {}", a.disk_type, b.disk_type));
     }
-    sum
+    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-    if args.scp_in.len() != 2 {
-        eprintln!("Two input scp files must be specified");
+fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Some(Command::Info(args)) => info::run(args),
+        Some(Command::Extract(args)) => extract::run(args),
+        Some(Command::Diff(args)) => diff::run(args),
+        Some(Command::Sectors(args)) => sectors::run(args),
+        Some(Command::Verify(args)) => verify_cmd::run(args),
+        None => weave(cli.weave),
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
         exit(1);
     }
-    let mut track_params: Vec<u8> = vec![0; 168];
-    for param in args.tracks {
-        let split: Vec<_> = param.split(":").collect();
-        track_params[split[0].parse::<usize>().unwrap() + split[1].parse::<usize>()?*2] = split[2].parse()?;
-    }
-    let mut scp_in_files: Vec<_> = args.scp_in.into_iter().map(|in_file| {
-        let mut file = File::open(&in_file).unwrap();
-        let header = ScpHeader::read(&mut file).unwrap();
-        if header.bitcell_time != 0 {
-            eprintln!("{in_file}: Unsupported bitcell time");
-            exit(1);
+}
+
+fn weave(mut args: Args) -> Result<(), Box<dyn Error>> {
+    if let Some(plan) = args.plan.clone() {
+        plan::apply_plan(&plan, &mut args)?;
+    }
+    if args.scp_in.is_empty() {
+        return Err("At least one input scp file must be specified".into());
+    }
+    if args.scp_out.is_none() {
+        return Err("An output scp file must be specified with -o".into());
+    }
+    run(args)
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let scp_in_files = args.scp_in.iter().map(ScpImage::open).collect::<Result<Vec<_>, _>>()?;
+    for scp in &scp_in_files {
+        match scp.verify_checksum() {
+            Err(e) if args.ignore_checksum => eprintln!("Warning: {e}"),
+            Err(e) => return Err(format!("{e} (use --ignore-checksum to weave it anyway)").into()),
+            Ok(()) => {}
         }
-        let tracks: Vec<_> = header.track_data_headers.into_iter().map(|offset| {
-            if offset != 0 {
-                file.seek(SeekFrom::Start(offset as u64)).unwrap();
-                let track = ScpTrack::read_args(&mut file, (header.rev_count,)).unwrap();
-                Some(track)
-            } else {
-                None
+        if let Err(e) = check_compatible(&scp_in_files[0], scp) {
+            if !args.force {
+                return Err(format!("{e} (use --force to weave them anyway)").into());
             }
-        }).collect();
-        Scp{file, header, tracks}
-    }).collect();
-
-    let mut scp_out_header = scp_in_files[0].header.clone();
-    scp_out_header.checksum = 0;
-    let mut out_file = File::create(args.scp_out)?;
-    let mut sum: u32 = 0;
-    scp_out_header.write(&mut out_file)?; // initial write, will be updated
+            eprintln!("Warning: {e}");
+        }
+    }
+
+    let heads = combined_heads(&scp_in_files);
+    let inputs = scp_in_files.len();
+    let mut track_params: Vec<Option<usize>> = vec![None; 168];
+    for param in args.tracks {
+        let (spec, source) = param.split_once('=').ok_or(format!("{param}: expected tracks=source"))?;
+        let source = parse_source(&param, source, inputs)?;
+        for track in parse_tracks(spec, scp_in_files[source].header.heads)? {
+            track_params[track] = Some(source);
+        }
+    }
+    let mut rev_params: Vec<Option<Vec<(usize, usize)>>> = vec![None; 168];
+    for param in args.revs {
+        let (spec, rev_list) = param.split_once('=').ok_or(format!("{param}: expected tracks=source/rev,..."))?;
+        let mut revs = Vec::new();
+        for rev in rev_list.split(',') {
+            let (source, rev) = rev.split_once('/').ok_or(format!("{param}: revolutions must be given as source/rev"))?;
+            revs.push((parse_source(&param, source, inputs)?, rev.parse().map_err(|e| format!("{param}: {e}"))?));
+        }
+        for track in parse_tracks(spec, heads)? {
+            rev_params[track] = Some(revs.clone());
+        }
+    }
+    let default_source = args.default_source.unwrap_or(0);
+    if default_source >= inputs {
+        return Err(format!("--default-source: source {default_source} out of range, {inputs} input files specified").into());
+    }
+    let encodings = parse_encodings(&args.encoding, heads)?;
+    let mut sector_params = [false; 168];
+    for param in args.sectors {
+        for track in parse_tracks(&param, heads)? {
+            sector_params[track] = true;
+        }
+    }
+
+    let resolution = args.resolution
+        .unwrap_or_else(|| scp_in_files.iter().map(|scp| scp.header.resolution).min().unwrap());
+    let mut scp_out_header = scp_in_files[0].header;
+    scp_out_header.resolution = resolution;
+    let sample_ns = scp_out_header.sample_ns();
+
+    let explicit_revs: Vec<bool> = rev_params.iter().map(Option::is_some).collect();
+    let mut track_sources: Vec<Option<TrackSource>> = (0..168).map(|_| None).collect();
+    // tracks picked with -t that no input has
+    let mut missing = Vec::new();
     for i in 0..168 {
-        if scp_in_files[0].tracks[i].is_none() {
-            scp_out_header.track_data_headers[i] = 0;
-            continue;
+        if let Some(revs) = rev_params[i].take() {
+            for &(source, rev) in &revs {
+                let Some(track) = &scp_in_files[source].tracks[i] else {
+                    return Err(format!("Track {i} not present in source {source}").into());
+                };
+                if rev >= track.revs.len() {
+                    return Err(format!("Track {i}: revolution {rev} out of range, source {source} has {} revolutions",
+                                       track.revs.len()).into());
+                }
+            }
+            track_sources[i] = Some(TrackSource::Revs(revs));
+        } else if sector_params[i] {
+            let weave = weave_sectors(&scp_in_files, i, encodings[i], sample_ns)?;
+            for SectorCopy { source, rev, sector, .. } in &weave.sectors {
+                let status = if sector.data_ok { "" } else { " (CRC error)" };
+                println!("Track {i}: sector {}.{}.{} from source {source} rev {rev}{status}",
+                         sector.cyl, sector.head, sector.record);
+            }
+            track_sources[i] = Some(TrackSource::Synth(weave.flux, weave.duration));
+        } else {
+            let source = match track_params[i] {
+                Some(source) if scp_in_files[source].tracks[i].is_some() => Some(source),
+                None if args.auto => auto_select(&scp_in_files, i, encodings[i])?.map(|(source, score)| {
+                    let sectors = if score.sectors > 0.0 {
+                        format!("{:.1}/{:.1} sectors good, ", score.good_sectors, score.sectors)
+                    } else {
+                        String::new()
+                    };
+                    println!("Track {i}: source {source} ({sectors}{:.1} RPM, {:.2}% spread)",
+                             score.rpm, score.spread * 100.0);
+                    source
+                }),
+                // fall through to the first input holding the track
+                source => {
+                    let fallback = std::iter::once(default_source).chain(0..inputs)
+                        .find(|&s| scp_in_files[s].tracks[i].is_some());
+                    match (source, fallback) {
+                        (Some(source), Some(fallback)) => {
+                            eprintln!("Warning: track {i} not present in source {source}, using source {fallback}");
+                        }
+                        (Some(_), None) => missing.push(i.to_string()),
+                        _ => {}
+                    }
+                    fallback
+                }
+            };
+            let Some(source) = source else { continue };
+            let rev_count = scp_in_files[source].header.rev_count as usize;
+            track_sources[i] = Some(TrackSource::Revs((0..rev_count).map(|rev| (source, rev)).collect()));
         }
-        let source_file = &mut scp_in_files[track_params[i] as usize];
-        let mut new_track = (*source_file).tracks[i].clone().unwrap();
-        let track_header_pos = out_file.stream_position()?;
-        scp_out_header.track_data_headers[i] = track_header_pos as u32;
-        new_track.write(&mut out_file)?;
-        for (j, rev) in new_track.revs.iter_mut().enumerate() {
-            // get flux data from source file
-            source_file.file.seek(SeekFrom::Start(source_file.header.track_data_headers[i] as u64
-                                                  + (*source_file).tracks[i].clone().unwrap().revs[j].offset as u64))?;
-            let mut flux_data = vec![0; source_file.tracks[i].clone().unwrap().revs[j].num_bitcells as usize * 2];
-            source_file.file.read_exact(&mut flux_data)?;
-            let flux_pos = out_file.stream_position()? - track_header_pos;
-            rev.offset = flux_pos as u32;
-            sum = sum.wrapping_add(checksum(&flux_data));
-            out_file.write_all(&flux_data)?;
+    }
+    if !missing.is_empty() {
+        eprintln!("Warning: tracks picked with -t but present in no input, skipping them: {}", missing.join(" "));
+    }
+    if let Some(disk_type) = args.disk_type {
+        scp_out_header.disk_type = disk_type.into();
+    }
+    scp_out_header.flags.set(ScpFlags::EXTENDED, args.extended);
+    if scp_in_files.iter().any(|scp| scp.sample_bits() != scp_out_header.sample_bits()) {
+        scp_out_header.bitcell_time = 0;
+    }
+    // revolution counts of the tracks picked with -r, to catch any cut short
+    let picked: Vec<(usize, usize)> = track_sources.iter().enumerate()
+        .filter_map(|(i, source)| match source {
+            Some(TrackSource::Revs(revs)) if explicit_revs[i] => Some((i, revs.len())),
+            _ => None,
+        })
+        .collect();
+    let rev_policy = args.rev_policy.unwrap_or(RevPolicy::Min);
+    if let Some(rev_count) = reconcile_revs(&mut track_sources, rev_policy, args.rev_count.map(usize::from))? {
+        for &(i, picked) in picked.iter().filter(|&&(_, picked)| picked > rev_count) {
+            let message = format!("Track {i}: {picked} revolutions selected with -r, but the output holds {rev_count}");
+            if rev_policy == RevPolicy::Min {
+                return Err(format!("{message} (use --rev-policy pad or truncate)").into());
+            }
+            eprintln!("Warning: {message}, dropping the rest");
         }
-        out_file.seek(SeekFrom::Start(track_header_pos))?;
-        let mut track_header_data = Cursor::new(Vec::<u8>::new());
-        new_track.write(&mut track_header_data)?; // rewrite track
-        sum = sum.wrapping_add(checksum(&track_header_data.get_ref()));
-        out_file.write_all(&track_header_data.get_ref())?;
-        out_file.seek(SeekFrom::End(0)).unwrap();
-    }
-    let mut header_for_checksum = Cursor::new(Vec::<u8>::new());
-    scp_out_header.write(&mut header_for_checksum)?;
-    sum = sum.wrapping_add(checksum(&header_for_checksum.get_ref()[0x10..]));
-    scp_out_header.checksum = sum;
-    out_file.seek(SeekFrom::Start(0))?;
-    scp_out_header.write(&mut out_file)?; // rewrite output header
+        scp_out_header.rev_count = rev_count as u8;
+    }
+    let mut writer = ScpWriter::new(File::create(args.scp_out.unwrap())?, scp_out_header)?;
+    write_tracks(&scp_in_files, &track_sources, &mut writer)?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut footer = scp_in_files.iter().find_map(|scp| scp.footer.clone());
+    let footer_args = [&args.manufacturer, &args.model, &args.serial, &args.creator, &args.comments];
+    if footer.is_none() && footer_args.iter().any(|arg| arg.is_some()) {
+        footer = Some(ScpFooter { creation_time: now, format_revision: scp_out_header.version, ..Default::default() });
+    }
+    if let Some(mut footer) = footer {
+        footer.manufacturer = args.manufacturer.or(footer.manufacturer);
+        footer.model = args.model.or(footer.model);
+        footer.serial = args.serial.or(footer.serial);
+        footer.creator = args.creator.or(footer.creator);
+        footer.comments = args.comments.or(footer.comments);
+        write_footer(&mut writer, footer)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+// Marks the footer as modified by scpweave and appends it to the output.
+fn write_footer<W: Write + Seek>(writer: &mut ScpWriter<W>, mut footer: ScpFooter) -> Result<(), Box<dyn Error>> {
+    footer.application_name = Some("scpweave".into());
+    footer.application_version = env!("CARGO_PKG_VERSION_MAJOR").parse::<u8>()? << 4
+        | env!("CARGO_PKG_VERSION_MINOR").parse::<u8>()?;
+    footer.modification_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    writer.write_footer(&footer)?;
     Ok(())
 }
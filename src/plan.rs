@@ -0,0 +1,126 @@
+// Weave plan files: a TOML or JSON recipe naming the inputs and the source of
+// each track, translated into the equivalent command line arguments.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::num::NonZeroU8;
+use std::path::Path;
+use crate::Args;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Plan {
+    output: Option<String>,
+    default: Option<String>,
+    #[serde(default)]
+    auto: bool,
+    rev_policy: Option<String>,
+    rev_count: Option<NonZeroU8>,
+    #[serde(default)]
+    header: HeaderOverrides,
+    inputs: Vec<PlanInput>,
+    #[serde(default)]
+    tracks: Vec<PlanTracks>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct HeaderOverrides {
+    disk_type: Option<u8>,
+    resolution: Option<u8>,
+    #[serde(default)]
+    extended: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct PlanInput {
+    name: String,
+    path: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct PlanTracks {
+    tracks: String,
+    source: Option<String>,
+    revs: Option<Vec<String>>,
+    #[serde(default)]
+    sectors: bool,
+}
+
+fn read_plan(path: &Path) -> Result<Plan, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let plan = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&text).map_err(|e| e.to_string())
+    } else {
+        toml::from_str(&text).map_err(|e| e.to_string())
+    };
+    plan.map_err(|e| format!("{}: {e}", path.display()))
+}
+
+// Fills in args from the plan file. Tracks from the plan come first so that
+// -t, -r and -s given on the command line take precedence, as do options.
+pub fn apply_plan(path: &str, args: &mut Args) -> Result<(), String> {
+    let path = Path::new(path);
+    let plan = read_plan(path)?;
+    if !args.scp_in.is_empty() {
+        return Err("Input files cannot be given on the command line when using --plan".into());
+    }
+    if plan.inputs.is_empty() {
+        return Err(format!("{}: no inputs specified", path.display()));
+    }
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut names = HashMap::new();
+    for (i, input) in plan.inputs.iter().enumerate() {
+        if names.insert(input.name.as_str(), i).is_some() {
+            return Err(format!("{}: input {} defined more than once", path.display(), input.name));
+        }
+        args.scp_in.push(dir.join(&input.path).to_string_lossy().into_owned());
+    }
+    let source = |name: &str| {
+        names.get(name).copied().ok_or(format!("{}: unknown input {name}", path.display()))
+    };
+
+    let (mut tracks, mut revs, mut sectors) = (Vec::new(), Vec::new(), Vec::new());
+    for entry in &plan.tracks {
+        let spec = &entry.tracks;
+        match (&entry.source, &entry.revs, entry.sectors) {
+            (Some(name), None, false) => tracks.push(format!("{spec}={}", source(name)?)),
+            (None, Some(list), false) => {
+                let list = list.iter()
+                    .map(|rev| {
+                        let (name, rev) = rev.split_once('/')
+                            .ok_or(format!("{spec}: revolutions must be given as input/rev"))?;
+                        Ok(format!("{}/{rev}", source(name)?))
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                revs.push(format!("{spec}={}", list.join(",")));
+            }
+            (None, None, true) => sectors.push(spec.clone()),
+            _ => return Err(format!("{spec}: expected exactly one of source, revs or sectors")),
+        }
+    }
+    tracks.append(&mut args.tracks);
+    revs.append(&mut args.revs);
+    sectors.append(&mut args.sectors);
+    (args.tracks, args.revs, args.sectors) = (tracks, revs, sectors);
+
+    if let Some(name) = &plan.default {
+        args.default_source = args.default_source.or(Some(source(name)?));
+    }
+    args.scp_out = args.scp_out.take().or(plan.output.map(|out| dir.join(out).to_string_lossy().into_owned()));
+    args.auto |= plan.auto;
+    if let Some(policy) = plan.rev_policy {
+        if args.rev_policy.is_none() {
+            args.rev_policy = Some(policy.parse()?);
+        }
+    }
+    args.rev_count = args.rev_count.or(plan.rev_count.map(u8::from));
+    args.disk_type = args.disk_type.or(plan.header.disk_type);
+    args.resolution = args.resolution.or(plan.header.resolution);
+    args.extended |= plan.header.extended;
+    Ok(())
+}
@@ -0,0 +1,220 @@
+use binrw::{binrw, BinRead};
+use bitflags::bitflags;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use crate::{DiskType, ScpError, ScpFooter};
+
+bitflags! {
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct ScpFlags: u8 {
+        /// Reads started at the index pulse
+        const INDEX = 0x01;
+        /// 96 TPI drive, otherwise 48 TPI
+        const TPI_96 = 0x02;
+        /// 360 RPM drive, otherwise 300 RPM
+        const RPM_360 = 0x04;
+        /// Flux has been normalized, otherwise preservation quality
+        const NORMALIZED = 0x08;
+        /// Image is read/write capable, otherwise read only
+        const READ_WRITE = 0x10;
+        const FOOTER = 0x20;
+        /// Extended mode for media other than floppy disks, with the track
+        /// offset table at 0x80
+        const EXTENDED = 0x40;
+        /// Flux created by a device other than a SuperCard Pro
+        const OTHER_DEVICE = 0x80;
+    }
+}
+
+#[binrw]
+#[brw(little, magic=b"SCP")]
+#[derive(Debug, Copy, Clone)]
+pub struct ScpHeader {
+    pub version: u8,
+    #[br(map = |code: u8| DiskType::from(code))]
+    #[bw(map = |disk_type| u8::from(*disk_type))]
+    pub disk_type: DiskType,
+    pub rev_count: u8,
+    pub start_track: u8,
+    pub end_track: u8,
+    #[br(map = |bits: u8| ScpFlags::from_bits_retain(bits))]
+    #[bw(map = |flags| flags.bits())]
+    pub flags: ScpFlags,
+    pub bitcell_time: u8,
+    pub heads: u8,
+    pub resolution: u8,
+    pub checksum: u32,
+    /// Track offset table, at 0x10 or at 0x80 in extended mode
+    #[brw(pad_before = if flags.contains(ScpFlags::EXTENDED) { 0x70 } else { 0 })]
+    pub track_data_headers: [u32; 168],
+}
+
+impl ScpHeader {
+    /// Width of each flux sample in bits, 8 or 16.
+    pub fn sample_bits(&self) -> u32 {
+        match self.bitcell_time {
+            0 => 16,
+            bits => bits as u32,
+        }
+    }
+
+    /// Sample period of the flux data in nanoseconds.
+    pub fn sample_ns(&self) -> f64 {
+        25.0 * (self.resolution as f64 + 1.0)
+    }
+
+    /// Drive speed the image was captured at.
+    pub fn rpm(&self) -> u32 {
+        if self.flags.contains(ScpFlags::RPM_360) { 360 } else { 300 }
+    }
+
+    pub fn tpi(&self) -> u32 {
+        if self.flags.contains(ScpFlags::TPI_96) { 96 } else { 48 }
+    }
+}
+
+#[binrw]
+#[brw(little, magic=b"TRK", import(rev_count: u8))]
+#[derive(Debug, Clone)]
+pub struct ScpTrack {
+    pub track_number: u8,
+    #[br(args { count: rev_count.into(), inner: () })]
+    pub revs: Vec<ScpRev>,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Copy, Clone)]
+pub struct ScpRev {
+    pub duration: u32,
+    pub num_bitcells: u32,
+    pub offset: u32,
+}
+
+/// An SCP image opened for reading. Flux data is read from the file on demand.
+pub struct ScpImage {
+    file: File,
+    path: PathBuf,
+    pub header: ScpHeader,
+    /// Track headers indexed by SCP track number (`cyl * 2 + head`).
+    pub tracks: Vec<Option<ScpTrack>>,
+    pub footer: Option<ScpFooter>,
+}
+
+impl ScpImage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ScpImage, ScpError> {
+        let path = path.as_ref();
+        Self::read(path).map_err(|e| e.in_file(path))
+    }
+
+    fn read(path: &Path) -> Result<ScpImage, ScpError> {
+        let mut file = File::open(path)?;
+        let header = ScpHeader::read(&mut file).map_err(|e| match e {
+            binrw::Error::BadMagic { .. } => ScpError::BadMagic,
+            e if is_eof(&e) => ScpError::TruncatedHeader,
+            e => e.into(),
+        })?;
+        if !matches!(header.bitcell_time, 0 | 8 | 16) {
+            return Err(ScpError::UnsupportedBitcellTime(header.bitcell_time));
+        }
+        let mut tracks = Vec::with_capacity(168);
+        for (track, offset) in header.track_data_headers.into_iter().enumerate() {
+            if offset == 0 {
+                tracks.push(None);
+                continue;
+            }
+            let offset = offset as u64;
+            file.seek(SeekFrom::Start(offset))?;
+            let scp_track = ScpTrack::read_args(&mut file, (header.rev_count,)).map_err(|e| match e {
+                binrw::Error::BadMagic { .. } => ScpError::BadTrackMagic { track, offset },
+                e if is_eof(&e) => ScpError::TruncatedTrack { track, offset },
+                e => e.into(),
+            })?;
+            if scp_track.track_number as usize != track {
+                return Err(ScpError::BadTrackNumber { track, found: scp_track.track_number });
+            }
+            tracks.push(Some(scp_track));
+        }
+        let footer = match header.flags.contains(ScpFlags::FOOTER) {
+            true => Some(ScpFooter::read(&mut file)?),
+            false => None,
+        };
+        Ok(ScpImage { file, path: path.to_path_buf(), header, tracks, footer })
+    }
+
+    /// Computes the checksum of the image as stored, to compare with
+    /// `header.checksum`.
+    pub fn compute_checksum(&self) -> Result<u32, ScpError> {
+        let mut file = &self.file;
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(0x10))
+            .and_then(|_| file.read_to_end(&mut data))
+            .map_err(|e| ScpError::from(e).in_file(&self.path))?;
+        Ok(checksum(&data))
+    }
+
+    /// Fails with `ScpError::BadChecksum` if the stored checksum does not match.
+    pub fn verify_checksum(&self) -> Result<(), ScpError> {
+        let found = self.compute_checksum()?;
+        if found != self.header.checksum {
+            let error = ScpError::BadChecksum { expected: self.header.checksum, found };
+            return Err(error.in_file(&self.path));
+        }
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Width of each flux sample in bits, 8 or 16.
+    pub fn sample_bits(&self) -> u32 {
+        self.header.sample_bits()
+    }
+
+    pub fn track(&self, cyl: u8, head: u8) -> Option<&ScpTrack> {
+        self.tracks.get(cyl as usize * 2 + head as usize)?.as_ref()
+    }
+
+    /// Sample period of the flux data in nanoseconds.
+    pub fn sample_ns(&self) -> f64 {
+        self.header.sample_ns()
+    }
+
+    /// Reads the raw flux samples of a revolution, `sample_bits` wide, where 0
+    /// marks an overflow of one full sample range.
+    pub fn revolution_flux(&self, track: usize, rev: usize) -> Result<Vec<u16>, ScpError> {
+        let scp_track = self.tracks.get(track).and_then(Option::as_ref)
+            .ok_or_else(|| ScpError::NoTrack { track }.in_file(&self.path))?;
+        let scp_rev = *scp_track.revs.get(rev)
+            .ok_or_else(|| ScpError::BadRevolution { track, rev }.in_file(&self.path))?;
+        let offset = self.header.track_data_headers[track] as u64 + scp_rev.offset as u64;
+        let mut file = &self.file;
+        let mut flux_data = vec![0; scp_rev.num_bitcells as usize * self.sample_bits() as usize / 8];
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut flux_data))
+            .map_err(|e| match e.kind() {
+                ErrorKind::UnexpectedEof => ScpError::TruncatedFlux { track, rev, offset },
+                _ => e.into(),
+            })
+            .map_err(|e| e.in_file(&self.path))?;
+        Ok(match self.sample_bits() {
+            8 => flux_data.into_iter().map(u16::from).collect(),
+            _ => flux_data.chunks_exact(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect(),
+        })
+    }
+}
+
+fn is_eof(e: &binrw::Error) -> bool {
+    matches!(e, binrw::Error::Io(e) if e.kind() == ErrorKind::UnexpectedEof)
+}
+
+pub fn checksum(data: &[u8]) -> u32 {
+    let mut sum: u32 = 0;
+    for &byte in data {
+        sum = sum.wrapping_add(byte as u32);
+    }
+    sum
+}
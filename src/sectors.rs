@@ -0,0 +1,157 @@
+// The sectors subcommand: decodes IBM MFM/FM, Amiga or 1541 GCR sectors from
+// every revolution of an image and reports their status.
+
+use clap::Args;
+use std::collections::BTreeMap;
+use std::error::Error;
+use scpweave::amiga::{self, AmigaStatus};
+use scpweave::c64::{self, GcrStatus};
+use scpweave::flux::{decode_revolution, PllConfig};
+use scpweave::ibm::{decode_sectors, Encoding, SectorStatus};
+use scpweave::tracks::parse_tracks;
+use scpweave::weave::{detect_format, read_revs};
+use scpweave::{DiskType, ScpError, ScpImage};
+
+#[derive(Args, Debug)]
+pub struct SectorsArgs {
+    scp: String,
+
+    /// Tracks to decode, as cyl.head or #track like the weave's -t [default: all]
+    #[arg(short('t'))]
+    tracks: Vec<String>,
+
+    /// Decode some tracks as FM or MFM instead of detecting the encoding:
+    /// tracks=fm or tracks=mfm
+    #[arg(short('e'), long)]
+    encoding: Vec<String>,
+}
+
+pub fn run(args: SectorsArgs) -> Result<(), Box<dyn Error>> {
+    let scp = ScpImage::open(&args.scp)?;
+    let encodings = crate::parse_encodings(&args.encoding, scp.header.heads)?;
+    let mut selected = [args.tracks.is_empty(); 168];
+    for spec in &args.tracks {
+        for track in parse_tracks(spec, scp.header.heads)? {
+            selected[track] = true;
+        }
+    }
+
+    let disk_type = scp.header.disk_type;
+    let (mut good, mut total) = (0, 0);
+    for i in (0..168).filter(|&i| selected[i] && scp.tracks[i].is_some()) {
+        print!("Track {i} ({}.{}): ", i / 2, i % 2);
+        let (track_good, track_total) = match amiga::layout(disk_type) {
+            Some((cell_ns, count)) => amiga_track(&scp, i, cell_ns, count)?,
+            None if disk_type == DiskType::C64 => c64_track(&scp, i)?,
+            None => ibm_track(&scp, i, encodings[i])?,
+        };
+        println!("  {track_good}/{track_total} sectors recovered");
+        good += track_good;
+        total += track_total;
+    }
+    println!("{good}/{total} sectors recovered");
+    Ok(())
+}
+
+fn problem_list(problems: Vec<String>) -> String {
+    match problems.is_empty() {
+        true => String::new(),
+        false => format!(": {}", problems.join(", ")),
+    }
+}
+
+// Prints the sectors of each revolution of an IBM track, returning the number
+// of sectors recovered in any revolution and the number seen.
+fn ibm_track(scp: &ScpImage, i: usize, encoding: Option<Encoding>) -> Result<(usize, usize), Box<dyn Error>> {
+    let Some((encoding, cell_ns)) = detect_format(&read_revs(std::slice::from_ref(scp), i)?, encoding) else {
+        println!("no sectors found");
+        return Ok((0, 0));
+    };
+    println!("{encoding} {cell_ns:.0}ns cells");
+    // whether a good copy of each sector was found in any revolution
+    let mut recovered = BTreeMap::new();
+    for rev in 0..scp.tracks[i].as_ref().unwrap().revs.len() {
+        let sectors = decode_sectors(&decode_revolution(scp, i, rev, &PllConfig::new(cell_ns))?, encoding);
+        let ok = sectors.iter().filter(|s| s.status() == SectorStatus::Ok).count();
+        let problems = sectors.iter()
+            .filter(|s| s.status() != SectorStatus::Ok)
+            .map(|s| format!("{}.{}.{} {}", s.cyl, s.head, s.record, s.status()))
+            .collect();
+        println!("  rev {rev}: {ok}/{} sectors ok{}", sectors.len(), problem_list(problems));
+        for sector in sectors.iter().filter(|s| s.id_ok) {
+            *recovered.entry((sector.cyl, sector.head, sector.record)).or_insert(false) |= sector.data_ok;
+        }
+    }
+    Ok((recovered.values().filter(|&&ok| ok).count(), recovered.len()))
+}
+
+// A sector of a format numbering sectors from 0, with the track number from
+// its header if the header is good.
+struct NumberedSector {
+    sector: u8,
+    track: Option<u8>,
+    ok: bool,
+    status: String,
+}
+
+// Prints the sectors of each revolution of a track of count sectors numbered
+// from 0, as ibm_track.
+fn numbered_track(revs: &[Vec<NumberedSector>], track: u8, count: usize) -> (usize, usize) {
+    let mut recovered = vec![false; count];
+    for (rev, sectors) in revs.iter().enumerate() {
+        let mut found = vec![false; count];
+        let mut problems = Vec::new();
+        for sector in sectors {
+            match sector.track {
+                Some(header_track) if header_track != track => {
+                    problems.push(format!("{} from track {header_track}", sector.sector));
+                }
+                Some(_) if sector.sector as usize >= count => problems.push(format!("{} out of range", sector.sector)),
+                _ if !sector.ok => problems.push(format!("{} {}", sector.sector, sector.status)),
+                _ => found[sector.sector as usize] = true,
+            }
+        }
+        let missing: Vec<_> = (0..count).filter(|&s| !sectors.iter().any(|f| f.sector as usize == s))
+            .map(|s| s.to_string())
+            .collect();
+        if !missing.is_empty() {
+            problems.push(format!("missing {}", missing.join(" ")));
+        }
+        let ok = found.iter().filter(|&&ok| ok).count();
+        println!("  rev {rev}: {ok}/{count} sectors ok{}", problem_list(problems));
+        for (recovered, found) in recovered.iter_mut().zip(found) {
+            *recovered |= found;
+        }
+    }
+    (recovered.iter().filter(|&&ok| ok).count(), count)
+}
+
+fn amiga_track(scp: &ScpImage, i: usize, cell_ns: f64, count: usize) -> Result<(usize, usize), Box<dyn Error>> {
+    println!("Amiga {cell_ns:.0}ns cells");
+    let revs = (0..scp.tracks[i].as_ref().unwrap().revs.len()).map(|rev| {
+        let sectors = amiga::decode_sectors(&decode_revolution(scp, i, rev, &PllConfig::new(cell_ns))?);
+        Ok(sectors.iter().map(|sector| NumberedSector {
+            sector: sector.sector,
+            track: sector.header_ok.then_some(sector.track),
+            ok: sector.status() == AmigaStatus::Ok,
+            status: sector.status().to_string(),
+        }).collect())
+    }).collect::<Result<Vec<_>, ScpError>>()?;
+    Ok(numbered_track(&revs, i as u8, count))
+}
+
+fn c64_track(scp: &ScpImage, i: usize) -> Result<(usize, usize), Box<dyn Error>> {
+    let (track, half) = c64::slot_track(&scp.header, i);
+    let (cell_ns, count) = c64::zone(track);
+    println!("1541 track {track}{} GCR {cell_ns:.0}ns cells", if half { ".5" } else { "" });
+    let revs = (0..scp.tracks[i].as_ref().unwrap().revs.len()).map(|rev| {
+        let sectors = c64::decode_sectors(&decode_revolution(scp, i, rev, &PllConfig::new(cell_ns))?);
+        Ok(sectors.iter().map(|sector| NumberedSector {
+            sector: sector.sector,
+            track: sector.header_ok.then_some(sector.track),
+            ok: sector.status() == GcrStatus::Ok,
+            status: sector.status().to_string(),
+        }).collect())
+    }).collect::<Result<Vec<_>, ScpError>>()?;
+    Ok(numbered_track(&revs, track, count))
+}
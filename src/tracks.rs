@@ -0,0 +1,77 @@
+use crate::ScpError;
+
+const CYLINDERS: usize = 84;
+
+/// Parses a track selection into SCP track numbers. Selections are `cyl.head`,
+/// where each part may be a number, a range `first-last` or `*`, or a raw
+/// track number or range prefixed with `#`. `heads` is the image header's
+/// heads field, limiting which sides exist in single sided images.
+pub fn parse_tracks(spec: &str, heads: u8) -> Result<Vec<usize>, ScpError> {
+    let error = |reason: String| ScpError::BadTrackSpec { spec: spec.to_string(), reason };
+    if let Some(tracks) = spec.strip_prefix('#') {
+        let (first, last) = parse_range(tracks, CYLINDERS * 2 - 1).map_err(error)?;
+        return Ok((first..=last).collect());
+    }
+    let Some((cyls, head)) = spec.split_once('.') else {
+        return Err(error("expected cyl.head or #track".into()));
+    };
+    let (first, last) = parse_range(cyls, CYLINDERS - 1).map_err(error)?;
+    let sides = match heads {
+        1 => vec![0],
+        2 => vec![1],
+        _ => vec![0, 1],
+    };
+    let sides = match head {
+        "*" => sides,
+        head => {
+            let head = parse_number(head, 1).map_err(error)?;
+            if !sides.contains(&head) {
+                return Err(error(format!("head {head} is not present in this single sided image")));
+            }
+            vec![head]
+        }
+    };
+    Ok((first..=last).flat_map(|cyl| sides.iter().map(move |head| cyl * 2 + head)).collect())
+}
+
+fn parse_range(range: &str, max: usize) -> Result<(usize, usize), String> {
+    if range == "*" {
+        return Ok((0, max));
+    }
+    let (first, last) = range.split_once('-').unwrap_or((range, range));
+    let (first, last) = (parse_number(first, max)?, parse_number(last, max)?);
+    if first > last {
+        return Err(format!("empty range {range}"));
+    }
+    Ok((first, last))
+}
+
+fn parse_number(number: &str, max: usize) -> Result<usize, String> {
+    let value: usize = number.parse().map_err(|_| format!("invalid number {number:?}"))?;
+    if value > max {
+        return Err(format!("{value} is out of range 0-{max}"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cylinder_range() {
+        assert_eq!(parse_tracks("0-39.0", 0).unwrap(), (0..40).map(|cyl| cyl * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn missing_side() {
+        assert!(parse_tracks("*.1", 1).is_err());
+        assert_eq!(parse_tracks("*.*", 1).unwrap().len(), CYLINDERS);
+    }
+
+    #[test]
+    fn raw_tracks() {
+        assert_eq!(parse_tracks("#167", 0).unwrap(), vec![167]);
+        assert!(parse_tracks("#168", 0).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use binrw::BinRead;
+use std::fmt;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use crate::scp::{checksum, ScpFlags, ScpHeader, ScpTrack};
+use crate::footer::FOOTER_LEN;
+use crate::{ScpError, ScpFooter};
+
+/// A structural problem found by `verify`, with the byte offset it concerns.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub offset: Option<u64>,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "{} at {offset:#x}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+struct Findings(Vec<Finding>);
+
+impl Findings {
+    fn add(&mut self, offset: Option<u64>, message: String) {
+        self.0.push(Finding { offset, message });
+    }
+}
+
+/// Checks the structure of an SCP image, returning everything found wrong
+/// with it. Fails only if the file cannot be read.
+pub fn verify<P: AsRef<Path>>(path: P) -> Result<Vec<Finding>, ScpError> {
+    let path = path.as_ref();
+    let data = fs::read(path).map_err(|e| ScpError::from(e).in_file(path))?;
+    let mut findings = Findings(Vec::new());
+    check(&data, &mut findings);
+    Ok(findings.0)
+}
+
+fn check(data: &[u8], findings: &mut Findings) {
+    let len = data.len() as u64;
+    if !data.starts_with(b"SCP") {
+        findings.add(Some(0), "bad magic, not an SCP image".into());
+        return;
+    }
+    let Ok(header) = ScpHeader::read(&mut Cursor::new(data)) else {
+        findings.add(Some(0), "truncated header".into());
+        return;
+    };
+    let version = header.version;
+    if !(1..=2).contains(&(version >> 4)) {
+        findings.add(Some(3), format!("unknown version {}.{}", version >> 4, version & 0xf));
+    }
+    if !matches!(header.bitcell_time, 0 | 8 | 16) {
+        findings.add(Some(9), format!("unsupported bitcell time {}", header.bitcell_time));
+    }
+    if header.rev_count == 0 {
+        findings.add(Some(5), "revolution count is 0".into());
+    }
+    if header.heads > 2 {
+        findings.add(Some(10), format!("unknown heads value {}", header.heads));
+    }
+    let computed = checksum(&data[0x10..]);
+    if computed != header.checksum {
+        findings.add(Some(0x0c), format!("checksum {:#010x} does not match computed {computed:#010x}", header.checksum));
+    }
+
+    // byte ranges in use, for the overlap check
+    let table_end = if header.flags.contains(ScpFlags::EXTENDED) { 0x80 } else { 0x10 } + 168 * 4;
+    let mut regions: Vec<(u64, u64, String)> = vec![(0, table_end, "header".into())];
+
+    if header.flags.contains(ScpFlags::FOOTER) {
+        match ScpFooter::read(&mut Cursor::new(data)) {
+            Ok(_) => regions.push((len - FOOTER_LEN, len, "footer".into())),
+            Err(e) => findings.add(None, e.to_string()),
+        }
+    } else if data.ends_with(b"FPCS") {
+        findings.add(Some(8), "footer present but footer flag not set".into());
+    }
+
+    let sample_bytes = header.sample_bits() as u64 / 8;
+    let mut populated = Vec::new();
+    for (i, &offset) in header.track_data_headers.iter().enumerate() {
+        if offset == 0 {
+            continue;
+        }
+        let offset = offset as u64;
+        let entry = Some(table_end - (168 - i as u64) * 4);
+        if offset < table_end || offset >= len {
+            findings.add(entry, format!("track {i}: offset {offset:#x} outside the track data"));
+            continue;
+        }
+        let mut cursor = Cursor::new(data);
+        cursor.set_position(offset);
+        let scp_track = match ScpTrack::read_args(&mut cursor, (header.rev_count,)) {
+            Ok(scp_track) => scp_track,
+            Err(binrw::Error::BadMagic { .. }) => {
+                findings.add(Some(offset), format!("track {i}: bad TRK magic"));
+                continue;
+            }
+            Err(_) => {
+                findings.add(Some(offset), format!("track {i}: truncated track header"));
+                continue;
+            }
+        };
+        populated.push(i);
+        if scp_track.track_number as usize != i {
+            findings.add(Some(offset + 3), format!("track {i}: track number {} does not match its slot", scp_track.track_number));
+        }
+        regions.push((offset, offset + 4 + 12 * scp_track.revs.len() as u64, format!("track {i} header")));
+        for (rev, scp_rev) in scp_track.revs.iter().enumerate() {
+            let rev_entry = Some(offset + 4 + 12 * rev as u64);
+            if scp_rev.duration == 0 {
+                findings.add(rev_entry, format!("track {i} rev {rev}: duration is 0"));
+            }
+            if scp_rev.num_bitcells == 0 {
+                findings.add(rev_entry, format!("track {i} rev {rev}: no flux data"));
+                continue;
+            }
+            let start = offset + scp_rev.offset as u64;
+            let end = start + scp_rev.num_bitcells as u64 * sample_bytes;
+            if end > len {
+                findings.add(rev_entry, format!("track {i} rev {rev}: flux data {start:#x}-{end:#x} extends past the end of the file"));
+            }
+            regions.push((start, end, format!("track {i} rev {rev} flux")));
+        }
+    }
+
+    regions.sort_by_key(|&(start, end, _)| (start, end));
+    let mut furthest = &regions[0];
+    for region in &regions[1..] {
+        if region.0 < furthest.1 {
+            findings.add(Some(region.0), format!("{} overlaps {}", region.2, furthest.2));
+        }
+        if region.1 > furthest.1 {
+            furthest = region;
+        }
+    }
+
+    if let (Some(&first), Some(&last)) = (populated.first(), populated.last()) {
+        if (first, last) != (header.start_track as usize, header.end_track as usize) {
+            findings.add(Some(6), format!("start and end track {}-{} do not match populated tracks {first}-{last}",
+                                          header.start_track, header.end_track));
+        }
+    }
+    let wrong_side = match header.heads {
+        1 => populated.iter().find(|&&i| i % 2 == 1),
+        2 => populated.iter().find(|&&i| i % 2 == 0),
+        _ => None,
+    };
+    if let Some(i) = wrong_side {
+        findings.add(Some(10), format!("track {i} present but heads field is {}", header.heads));
+    }
+}
@@ -0,0 +1,30 @@
+// The verify subcommand: runs the library's structural checks over images.
+// Named apart from the library's verify module, which src/verify.rs holds.
+
+use clap::Args;
+use std::error::Error;
+use scpweave::verify::verify;
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    #[arg(required = true)]
+    scp: Vec<String>,
+}
+
+pub fn run(args: VerifyArgs) -> Result<(), Box<dyn Error>> {
+    let mut problems = 0;
+    for path in &args.scp {
+        let findings = verify(path)?;
+        if findings.is_empty() {
+            println!("{path}: ok");
+        }
+        for finding in &findings {
+            println!("{path}: {finding}");
+        }
+        problems += findings.len();
+    }
+    if problems > 0 {
+        return Err(format!("{problems} problems found").into());
+    }
+    Ok(())
+}
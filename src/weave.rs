@@ -0,0 +1,333 @@
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::str::FromStr;
+use crate::amiga::{self, AmigaStatus};
+use crate::c64::{self, GcrStatus};
+use crate::flux::{convert_flux, encode_flux, pll_decode, revolution_intervals, PllConfig};
+use crate::ibm::{decode_sectors, encode_track, Encoding, Sector};
+use crate::{DiskType, ScpError, ScpImage, ScpRev, ScpWriter};
+
+pub enum TrackSource {
+    /// (source, revolution) pairs
+    Revs(Vec<(usize, usize)>),
+    /// Synthesized flux data and duration of a single revolution
+    Synth(Vec<u16>, u32),
+}
+
+/// How to settle on one revolution count when the chosen tracks differ.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RevPolicy {
+    /// Use the smallest count, truncating the other tracks
+    Min,
+    /// Truncate every track to a given count, failing if a track has fewer
+    Truncate,
+    /// Bring every track to a given count, repeating revolutions of tracks with
+    /// fewer
+    Pad,
+}
+
+impl FromStr for RevPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "min" => Ok(RevPolicy::Min),
+            "truncate" => Ok(RevPolicy::Truncate),
+            "pad" => Ok(RevPolicy::Pad),
+            _ => Err(format!("unknown revolution policy {s}, expected min, truncate or pad")),
+        }
+    }
+}
+
+pub struct SectorCopy {
+    pub source: usize,
+    pub rev: usize,
+    pub sector: Sector,
+    /// Angular position within the revolution, 0.0 to 1.0
+    pub position: f64,
+}
+
+// Cell periods tried when looking for IBM sectors: ED, HD, DD at 360 RPM, DD and
+// 5.25" FM.
+const CELL_PERIODS: [f64; 5] = [500.0, 1000.0, 1000.0 * 5.0 / 3.0, 2000.0, 4000.0];
+
+pub struct RevFlux {
+    pub source: usize,
+    pub rev: usize,
+    pub intervals: Vec<f64>,
+    pub duration_ns: f64,
+}
+
+/// Reads every revolution of a track from all inputs that have it.
+pub fn read_revs(inputs: &[ScpImage], track: usize) -> Result<Vec<RevFlux>, ScpError> {
+    let mut revs = Vec::new();
+    for (source, scp) in inputs.iter().enumerate() {
+        let Some(scp_track) = &scp.tracks[track] else { continue };
+        for (rev, scp_rev) in scp_track.revs.iter().enumerate() {
+            let intervals = revolution_intervals(scp, track, rev)?;
+            revs.push(RevFlux { source, rev, intervals, duration_ns: scp_rev.duration as f64 * scp.sample_ns() });
+        }
+    }
+    Ok(revs)
+}
+
+/// Picks the encoding and cell period finding the most sector IDs in the first
+/// revolution of each source, trying only `encoding` if given.
+pub fn detect_format(revs: &[RevFlux], encoding: Option<Encoding>) -> Option<(Encoding, f64)> {
+    let mut best = None;
+    let mut best_count = 0;
+    let encodings = match encoding {
+        Some(encoding) => vec![encoding],
+        None => vec![Encoding::Mfm, Encoding::Fm],
+    };
+    for encoding in encodings {
+        for cell_ns in CELL_PERIODS {
+            let count: usize = revs.iter().filter(|r| r.rev == 0).map(|r| {
+                decode_sectors(&pll_decode(&r.intervals, &PllConfig::new(cell_ns)), encoding).iter().filter(|s| s.id_ok).count()
+            }).sum();
+            if count > best_count {
+                best = Some((encoding, cell_ns));
+                best_count = count;
+            }
+        }
+    }
+    best
+}
+
+pub struct TrackScore {
+    /// Average sectors with good ID and data CRCs per revolution
+    pub good_sectors: f64,
+    /// Average sector IDs found per revolution
+    pub sectors: f64,
+    /// Largest relative deviation between revolutions in duration or flux count
+    pub spread: f64,
+    pub rpm: f64,
+}
+
+impl TrackScore {
+    pub fn index_ok(&self) -> bool {
+        [300.0, 360.0].iter().any(|rpm| (self.rpm - rpm).abs() / rpm < 0.03)
+    }
+
+    pub fn better_than(&self, other: &TrackScore) -> bool {
+        if self.good_sectors != other.good_sectors {
+            return self.good_sectors > other.good_sectors;
+        }
+        if self.index_ok() != other.index_ok() {
+            return self.index_ok();
+        }
+        self.spread < other.spread
+    }
+}
+
+/// Scores the revolutions of a track from one source, given a function
+/// returning the good and total sectors decoded from a revolution.
+pub fn score_track(revs: &[&RevFlux], count_sectors: impl Fn(&RevFlux) -> (usize, usize)) -> TrackScore {
+    let (good_sectors, sectors) = revs.iter()
+        .map(|rev| count_sectors(rev))
+        .fold((0, 0), |(good, total), (rev_good, rev_total)| (good + rev_good, total + rev_total));
+    let spread = |values: Vec<f64>| {
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        values.iter().map(|v| (v - mean).abs() / mean).fold(0.0, f64::max)
+    };
+    let durations: Vec<_> = revs.iter().map(|r| r.duration_ns).collect();
+    let mean_duration = durations.iter().sum::<f64>() / durations.len() as f64;
+    TrackScore {
+        good_sectors: good_sectors as f64 / revs.len() as f64,
+        sectors: sectors as f64 / revs.len() as f64,
+        spread: spread(durations).max(spread(revs.iter().map(|r| r.intervals.len() as f64).collect())),
+        rpm: 60e9 / mean_duration,
+    }
+}
+
+/// Scores a track in every input that has it, returning the best source and
+/// its score. Amiga and C64 disks are decoded as such; otherwise IBM sectors
+/// are decoded as `encoding` if given, or else detected.
+pub fn auto_select(inputs: &[ScpImage], track: usize, encoding: Option<Encoding>)
+                   -> Result<Option<(usize, TrackScore)>, ScpError> {
+    let revs = read_revs(inputs, track)?;
+    let header = &inputs[0].header;
+    let amiga = amiga::layout(header.disk_type);
+    let c64 = header.disk_type == DiskType::C64;
+    let format = if amiga.is_none() && !c64 { detect_format(&revs, encoding) } else { None };
+    let count_sectors = |rev: &RevFlux| {
+        if let Some((cell_ns, _)) = amiga {
+            let decoded = amiga::decode_sectors(&pll_decode(&rev.intervals, &PllConfig::new(cell_ns)));
+            let good = decoded.iter().filter(|s| s.status() == AmigaStatus::Ok && s.track as usize == track).count();
+            (good, decoded.iter().filter(|s| s.header_ok).count())
+        } else if c64 {
+            let (c64_track, _) = c64::slot_track(header, track);
+            let decoded = c64::decode_sectors(&pll_decode(&rev.intervals, &PllConfig::new(c64::zone(c64_track).0)));
+            let good = decoded.iter().filter(|s| s.status() == GcrStatus::Ok && s.track == c64_track).count();
+            (good, decoded.iter().filter(|s| s.header_ok).count())
+        } else if let Some((encoding, cell_ns)) = format {
+            let decoded = decode_sectors(&pll_decode(&rev.intervals, &PllConfig::new(cell_ns)), encoding);
+            (decoded.iter().filter(|s| s.id_ok && s.data_ok).count(), decoded.iter().filter(|s| s.id_ok).count())
+        } else {
+            (0, 0)
+        }
+    };
+    let mut best: Option<(usize, TrackScore)> = None;
+    for source in 0..inputs.len() {
+        let source_revs: Vec<_> = revs.iter().filter(|r| r.source == source).collect();
+        if source_revs.is_empty() {
+            continue;
+        }
+        let score = score_track(&source_revs, count_sectors);
+        if best.as_ref().is_none_or(|(_, best)| score.better_than(best)) {
+            best = Some((source, score));
+        }
+    }
+    Ok(best)
+}
+
+pub struct SectorWeave {
+    /// Sectors used, in track order
+    pub sectors: Vec<SectorCopy>,
+    pub flux: Vec<u16>,
+    pub duration: u32,
+}
+
+/// Builds a single revolution of a track from the best copy of each sector
+/// found in any revolution of any input, with 16-bit flux at the given sample
+/// period. Sectors are decoded as `encoding` if given, otherwise detected.
+pub fn weave_sectors(inputs: &[ScpImage], track: usize, encoding: Option<Encoding>, sample_ns: f64)
+                     -> Result<SectorWeave, ScpError> {
+    let revs = read_revs(inputs, track)?;
+    let Some((encoding, cell_ns)) = detect_format(&revs, encoding) else {
+        return Err(ScpError::NoSectors { track });
+    };
+
+    // best copy of each sector, keyed by ID
+    let mut chosen: BTreeMap<(u8, u8, u8, u8), SectorCopy> = BTreeMap::new();
+    for RevFlux { source, rev, intervals, .. } in &revs {
+        let bits = pll_decode(intervals, &PllConfig::new(cell_ns));
+        for sector in decode_sectors(&bits, encoding) {
+            if !sector.id_ok || sector.data.is_none() {
+                continue;
+            }
+            let key = (sector.cyl, sector.head, sector.record, sector.size);
+            if chosen.get(&key).is_none_or(|c| !c.sector.data_ok && sector.data_ok) {
+                let position = sector.position as f64 / bits.len() as f64;
+                chosen.insert(key, SectorCopy { source: *source, rev: *rev, sector, position });
+            }
+        }
+    }
+    let mut sectors: Vec<_> = chosen.into_values().collect();
+    sectors.sort_by(|a, b| a.position.total_cmp(&b.position));
+
+    let track_ns = revs.iter().map(|r| r.duration_ns).sum::<f64>() / revs.len() as f64;
+    let sector_refs: Vec<_> = sectors.iter().map(|s| &s.sector).collect();
+    let Some(bits) = encode_track(encoding, &sector_refs, (track_ns / cell_ns) as usize) else {
+        return Err(ScpError::SectorsDoNotFit { track });
+    };
+    let (flux, duration) = encode_flux(&bits, cell_ns, sample_ns);
+    Ok(SectorWeave { sectors, flux, duration })
+}
+
+/// Makes every copied track have the same number of revolutions according to
+/// `policy`, returning that number. `count` defaults to the smallest count for
+/// `Min` and `Truncate` and the largest for `Pad`; with `Min` it is an upper
+/// bound.
+pub fn reconcile_revs(sources: &mut [Option<TrackSource>], policy: RevPolicy, count: Option<usize>)
+                      -> Result<Option<usize>, ScpError> {
+    let counts = sources.iter().filter_map(|source| match source {
+        Some(TrackSource::Revs(revs)) => Some(revs.len()),
+        _ => None,
+    });
+    let rev_count = match policy {
+        RevPolicy::Min => counts.min().map(|min| count.map_or(min, |count| count.min(min))),
+        RevPolicy::Truncate => count.or(counts.min()),
+        RevPolicy::Pad => count.or(counts.max()),
+    };
+    let Some(rev_count) = rev_count else { return Ok(None) };
+    for (track, source) in sources.iter_mut().enumerate() {
+        let Some(TrackSource::Revs(revs)) = source else { continue };
+        if revs.len() < rev_count {
+            if policy != RevPolicy::Pad {
+                return Err(ScpError::TooFewRevs { track, found: revs.len(), needed: rev_count });
+            }
+            *revs = revs.iter().cycle().take(rev_count).copied().collect();
+        }
+        revs.truncate(rev_count);
+    }
+    Ok(Some(rev_count))
+}
+
+/// Writes every selected track, copying flux data from the inputs and
+/// converting it to the sample width and period of the writer's header.
+pub fn write_tracks<W: Write + Seek>(inputs: &[ScpImage], sources: &[Option<TrackSource>],
+                                     writer: &mut ScpWriter<W>) -> Result<(), ScpError> {
+    let header = *writer.header();
+    let (sample_bits, sample_ns) = (header.sample_bits(), header.sample_ns());
+    for (i, source) in sources.iter().enumerate() {
+        // revolutions with their flux data
+        let revs: Vec<(ScpRev, Vec<u16>)> = match source {
+            None => continue,
+            Some(TrackSource::Revs(revs)) => revs.iter().map(|&(source, rev)| {
+                let input = &inputs[source];
+                let mut scp_rev = input.tracks[i].as_ref().unwrap().revs[rev];
+                scp_rev.duration = (scp_rev.duration as f64 * input.sample_ns() / sample_ns).round() as u32;
+                let flux = input.revolution_flux(i, rev)?;
+                Ok((scp_rev, convert_flux(&flux, input.sample_bits(), input.sample_ns(), sample_bits, sample_ns)))
+            }).collect::<Result<_, ScpError>>()?,
+            Some(TrackSource::Synth(flux, duration)) => {
+                let scp_rev = ScpRev { duration: *duration, num_bitcells: 0, offset: 0 };
+                let flux = convert_flux(flux, 16, sample_ns, sample_bits, sample_ns);
+                vec![(scp_rev, flux); header.rev_count as usize]
+            }
+        };
+        writer.write_track(i, &revs)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(counts: &[usize]) -> Vec<Option<TrackSource>> {
+        counts.iter().map(|&count| Some(TrackSource::Revs((0..count).map(|rev| (0, rev)).collect()))).collect()
+    }
+
+    fn counts(sources: &[Option<TrackSource>]) -> Vec<usize> {
+        sources.iter().map(|source| match source {
+            Some(TrackSource::Revs(revs)) => revs.len(),
+            _ => 0,
+        }).collect()
+    }
+
+    #[test]
+    fn min_policy() {
+        let mut tracks = sources(&[3, 2, 5]);
+        assert_eq!(reconcile_revs(&mut tracks, RevPolicy::Min, None).unwrap(), Some(2));
+        assert_eq!(counts(&tracks), [2, 2, 2]);
+        let mut tracks = sources(&[3, 2, 5]);
+        assert_eq!(reconcile_revs(&mut tracks, RevPolicy::Min, Some(1)).unwrap(), Some(1));
+        assert_eq!(reconcile_revs(&mut sources(&[3, 2]), RevPolicy::Min, Some(4)).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn truncate_policy() {
+        let mut tracks = sources(&[3, 4]);
+        assert_eq!(reconcile_revs(&mut tracks, RevPolicy::Truncate, Some(3)).unwrap(), Some(3));
+        assert_eq!(counts(&tracks), [3, 3]);
+        assert!(matches!(reconcile_revs(&mut sources(&[3, 2]), RevPolicy::Truncate, Some(3)),
+                         Err(ScpError::TooFewRevs { track: 1, found: 2, needed: 3 })));
+    }
+
+    #[test]
+    fn pad_policy() {
+        let mut tracks = sources(&[1, 3]);
+        assert_eq!(reconcile_revs(&mut tracks, RevPolicy::Pad, None).unwrap(), Some(3));
+        assert_eq!(counts(&tracks), [3, 3]);
+        let Some(TrackSource::Revs(revs)) = &tracks[0] else { unreachable!() };
+        assert_eq!(revs, &[(0, 0), (0, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn no_copied_tracks() {
+        let mut tracks = vec![None, Some(TrackSource::Synth(Vec::new(), 0))];
+        assert_eq!(reconcile_revs(&mut tracks, RevPolicy::Min, None).unwrap(), None);
+    }
+}
@@ -0,0 +1,88 @@
+use binrw::BinWrite;
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+use crate::scp::{checksum, ScpFlags, ScpHeader, ScpRev, ScpTrack};
+use crate::{ScpError, ScpFooter};
+
+/// Writes an SCP image track by track, filling in the track offset table and
+/// checksum when finished.
+pub struct ScpWriter<W: Write + Seek> {
+    out: W,
+    header: ScpHeader,
+    sum: u32,
+}
+
+impl<W: Write + Seek> ScpWriter<W> {
+    pub fn new(mut out: W, mut header: ScpHeader) -> Result<Self, ScpError> {
+        header.checksum = 0;
+        header.flags.remove(ScpFlags::FOOTER);
+        header.track_data_headers = [0; 168];
+        header.write(&mut out)?; // initial write, will be updated
+        Ok(ScpWriter { out, header, sum: 0 })
+    }
+
+    pub fn header(&self) -> &ScpHeader {
+        &self.header
+    }
+
+    /// Appends a track. The `num_bitcells` and `offset` of each revolution are
+    /// taken from its flux data, which must be in the header's sample width.
+    pub fn write_track(&mut self, track: usize, revs: &[(ScpRev, Vec<u16>)]) -> Result<(), ScpError> {
+        let track_header_pos = self.out.stream_position()?;
+        let sample_bytes = self.header.sample_bits() / 8;
+        let mut offset = 4 + 12 * revs.len() as u32;
+        let new_track = ScpTrack {
+            track_number: track as u8,
+            revs: revs.iter().map(|(rev, flux)| {
+                let rev = ScpRev { num_bitcells: flux.len() as u32, offset, ..*rev };
+                offset += flux.len() as u32 * sample_bytes;
+                rev
+            }).collect(),
+        };
+        let mut track_data = Cursor::new(Vec::<u8>::new());
+        new_track.write(&mut track_data)?;
+        let track_data = track_data.get_mut();
+        for (_, flux) in revs {
+            match sample_bytes {
+                1 => track_data.extend(flux.iter().map(|&sample| sample as u8)),
+                _ => track_data.extend(flux.iter().flat_map(|sample| sample.to_be_bytes())),
+            }
+        }
+        self.sum = self.sum.wrapping_add(checksum(track_data));
+        self.out.write_all(track_data)?;
+        self.header.track_data_headers[track] = track_header_pos as u32;
+        Ok(())
+    }
+
+    /// Appends the extension footer. Must be called after the last track.
+    pub fn write_footer(&mut self, footer: &ScpFooter) -> Result<(), ScpError> {
+        let footer_pos = self.out.stream_position()?;
+        let footer_data = footer.to_bytes(footer_pos as u32)?;
+        self.sum = self.sum.wrapping_add(checksum(&footer_data));
+        self.out.write_all(&footer_data)?;
+        self.header.flags.insert(ScpFlags::FOOTER);
+        Ok(())
+    }
+
+    /// Sets the start and end track and the heads field from the tracks
+    /// written and fills in the header.
+    pub fn finish(mut self) -> Result<W, ScpError> {
+        let offsets = self.header.track_data_headers;
+        if let Some(start) = offsets.iter().position(|&offset| offset != 0) {
+            self.header.start_track = start as u8;
+            self.header.end_track = offsets.iter().rposition(|&offset| offset != 0).unwrap() as u8;
+            let side = |head| offsets.iter().skip(head).step_by(2).any(|&offset| offset != 0);
+            self.header.heads = match (side(0), side(1)) {
+                (true, false) => 1,
+                (false, true) => 2,
+                _ => 0,
+            };
+        }
+        let mut header_for_checksum = Cursor::new(Vec::<u8>::new());
+        self.header.write(&mut header_for_checksum)?;
+        self.header.checksum = self.sum.wrapping_add(checksum(&header_for_checksum.get_ref()[0x10..]));
+        self.out.seek(SeekFrom::Start(0))?;
+        self.header.write(&mut self.out)?; // rewrite output header
+        Ok(self.out)
+    }
+}